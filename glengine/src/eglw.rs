@@ -4,6 +4,7 @@ use egl::types::*;
 use std::mem;
 use std::ptr;
 use std::ffi::CStr;
+use error::Error;
 
 pub struct Display
 {
@@ -14,17 +15,17 @@ pub struct Display
 
 impl Display
 {
-    pub fn new(disp: NativeDisplayType) -> Result<Self, &'static str>
+    pub fn new(disp: NativeDisplayType) -> Result<Self, Error>
     {
         let egl_disp = unsafe { egl::GetDisplay(disp) };
         if egl_disp == egl::NO_DISPLAY
         {
-            return Err("error opening EGL display")
+            return Err(Error::last_egl("eglGetDisplay"))
         }
 
         if unsafe { egl::Initialize(egl_disp, ptr::null_mut(), ptr::null_mut()) } == 0
         {
-            return Err("error initializing EGL")
+            return Err(Error::last_egl("eglInitialize"))
         }
 
         unsafe
@@ -51,11 +52,11 @@ impl Display
         let mut num_cfg = 0;
         if unsafe { egl::ChooseConfig(egl_disp, cfg_attribs.as_ptr() as _, configs.as_ptr() as *mut _, configs.len() as EGLint, &mut num_cfg) } == 0
         {
-            return Err("error choosing EGL config")
+            return Err(Error::last_egl("eglChooseConfig"))
         }
         if num_cfg == 0
         {
-            return Err("no compatible EGL configs found")
+            return Err(Error::NoConfig)
         }
 
         let ctx_attribs = [
@@ -65,13 +66,13 @@ impl Display
         let context = unsafe { egl::CreateContext(egl_disp, configs[0], egl::NO_CONTEXT, ctx_attribs.as_ptr() as _) };
         if context == egl::NO_CONTEXT
         {
-            return Err("error creating GL context")
+            return Err(Error::last_egl("eglCreateContext"))
         }
 
         // we need to bind a context before making any GL call
         if unsafe { egl::MakeCurrent(egl_disp, egl::NO_SURFACE, egl::NO_SURFACE, context) } == 0
         {
-            return Err("error binding GL context")
+            return Err(Error::last_egl("eglMakeCurrent"))
         }
 
         Ok(Display{
@@ -89,12 +90,12 @@ impl Display
     }
     */
 
-    pub fn create_window_surface(&self, win: NativeWindowType) -> Result<Surface, &'static str>
+    pub fn create_window_surface(&self, win: NativeWindowType) -> Result<Surface, Error>
     {
         let surface = unsafe { egl::CreateWindowSurface(self.egl_disp, self.egl_config, win, ptr::null()) };
         if surface == egl::NO_SURFACE
         {
-            return Err("can't create EGL surface")
+            return Err(Error::last_egl("eglCreateWindowSurface"))
         }
         Ok(Surface{ id: surface, disp: self })
     }
@@ -0,0 +1,59 @@
+use egl;
+use egl::types::*;
+use std::fmt;
+use std::error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error
+{
+    Egl(&'static str /* failed call */, EGLint /* eglGetError code */),
+    NoConfig,
+    ShaderCompile(&'static str /* shader stage */, String /* info log */),
+    ProgramLink(String /* info log */),
+}
+
+impl Error
+{
+    // builds the error from the last EGL error on this thread
+    pub fn last_egl(call: &'static str) -> Self
+    {
+        Error::Egl(call, unsafe { egl::GetError() })
+    }
+}
+
+fn egl_error_name(code: EGLint) -> &'static str
+{
+    match code as GLenum {
+        egl::SUCCESS => "EGL_SUCCESS",
+        egl::NOT_INITIALIZED => "EGL_NOT_INITIALIZED",
+        egl::BAD_ACCESS => "EGL_BAD_ACCESS",
+        egl::BAD_ALLOC => "EGL_BAD_ALLOC",
+        egl::BAD_ATTRIBUTE => "EGL_BAD_ATTRIBUTE",
+        egl::BAD_CONTEXT => "EGL_BAD_CONTEXT",
+        egl::BAD_CONFIG => "EGL_BAD_CONFIG",
+        egl::BAD_CURRENT_SURFACE => "EGL_BAD_CURRENT_SURFACE",
+        egl::BAD_DISPLAY => "EGL_BAD_DISPLAY",
+        egl::BAD_SURFACE => "EGL_BAD_SURFACE",
+        egl::BAD_MATCH => "EGL_BAD_MATCH",
+        egl::BAD_PARAMETER => "EGL_BAD_PARAMETER",
+        egl::BAD_NATIVE_PIXMAP => "EGL_BAD_NATIVE_PIXMAP",
+        egl::BAD_NATIVE_WINDOW => "EGL_BAD_NATIVE_WINDOW",
+        egl::CONTEXT_LOST => "EGL_CONTEXT_LOST",
+        _ => "unknown EGL error",
+    }
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            Error::Egl(call, code) => write!(f, "{} failed: {} (0x{:x})", call, egl_error_name(code), code),
+            Error::NoConfig => write!(f, "no compatible EGL configs found"),
+            Error::ShaderCompile(stage, ref log) => write!(f, "{} shader compilation failed: {}", stage, log),
+            Error::ProgramLink(ref log) => write!(f, "shader program link failed: {}", log),
+        }
+    }
+}
+
+impl error::Error for Error {}
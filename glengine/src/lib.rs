@@ -7,6 +7,7 @@ mod types;
 mod typeinfo;
 mod shader;
 mod eglw;
+mod error;
 
 use std::mem;
 use std::ffi::CStr;
@@ -21,6 +22,7 @@ pub use egl::NativeDisplayType;
 pub use egl::NativeWindowType;
 pub use eglw::Surface;
 pub use types::{Point, Color, TexCoord, Rect};
+pub use error::Error;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -51,7 +53,7 @@ pub struct DrawEngine
 
 impl DrawEngine
 {
-    pub fn new(xdisp: NativeDisplayType) -> Result<Self, &'static str>
+    pub fn new(xdisp: NativeDisplayType) -> Result<Self, Error>
     {
         let egl_disp = eglw::Display::new(xdisp)?;
 
@@ -65,11 +67,12 @@ impl DrawEngine
                 vendor, renderer, version, exts);
         }
 
+        let prog = Program::new(&[
+            Shader::new(gl::VERTEX_SHADER, &[include_str!("test.vert.glsl")]).map_err(|e| Error::ShaderCompile("vertex", e))?,
+            Shader::new(gl::FRAGMENT_SHADER, &[include_str!("test.frag.glsl")]).map_err(|e| Error::ShaderCompile("fragment", e))?,
+        ]).map_err(Error::ProgramLink)?;
+
         let mut eng = unsafe {
-            let prog = Program::new(&[
-                Shader::new(gl::VERTEX_SHADER, &[include_str!("test.vert.glsl")]).unwrap_or_else(|e| panic!("vert: {}", e)),
-                Shader::new(gl::FRAGMENT_SHADER, &[include_str!("test.frag.glsl")]).unwrap_or_else(|e| panic!("frag: {}", e)),
-            ]).unwrap_or_else(|e| panic!("link: {}", e));
             prog.set_active();
 
             // vertex buffer
@@ -116,7 +119,7 @@ impl DrawEngine
         Ok(eng)
     }
 
-    pub fn create_window_surface(&self, win: NativeWindowType) -> Result<Surface, &'static str>
+    pub fn create_window_surface(&self, win: NativeWindowType) -> Result<Surface, Error>
     {
         self.egl_disp.create_window_surface(win)
     }
@@ -0,0 +1,131 @@
+// cursor images: theme shapes through Xcursor, core font fallbacks and custom RGBA images
+use x11::{xlib, xcursor};
+use std::mem;
+use std::slice;
+use std::os::raw::{c_char, c_int, c_uint};
+
+// a cursor created by XDisplay, valid until it's freed or the display is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CursorId(pub(crate) xlib::Cursor);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CursorShape
+{
+    Arrow,
+    Text,
+    Hand,
+    Crosshair,
+    Move,
+    Wait,
+    Help,
+    NotAllowed,
+    ResizeHorizontal,
+    ResizeVertical,
+    ResizeNwSe,
+    ResizeNeSw,
+}
+
+// from cursorfont.h
+const XC_X_CURSOR: c_uint = 0;
+const XC_BOTTOM_LEFT_CORNER: c_uint = 12;
+const XC_BOTTOM_RIGHT_CORNER: c_uint = 14;
+const XC_CROSSHAIR: c_uint = 34;
+const XC_FLEUR: c_uint = 52;
+const XC_HAND2: c_uint = 60;
+const XC_LEFT_PTR: c_uint = 68;
+const XC_QUESTION_ARROW: c_uint = 92;
+const XC_SB_H_DOUBLE_ARROW: c_uint = 108;
+const XC_SB_V_DOUBLE_ARROW: c_uint = 116;
+const XC_WATCH: c_uint = 150;
+const XC_XTERM: c_uint = 152;
+
+impl CursorShape
+{
+    // the freedesktop cursor spec names first, then the older ones some themes only have
+    fn theme_names(&self) -> &'static [&'static [u8]]
+    {
+        match *self {
+            CursorShape::Arrow => &[b"default\0", b"left_ptr\0"],
+            CursorShape::Text => &[b"text\0", b"xterm\0"],
+            CursorShape::Hand => &[b"pointer\0", b"hand2\0"],
+            CursorShape::Crosshair => &[b"crosshair\0"],
+            CursorShape::Move => &[b"move\0", b"fleur\0"],
+            CursorShape::Wait => &[b"wait\0", b"watch\0"],
+            CursorShape::Help => &[b"help\0", b"question_arrow\0"],
+            CursorShape::NotAllowed => &[b"not-allowed\0", b"crossed_circle\0"],
+            CursorShape::ResizeHorizontal => &[b"ew-resize\0", b"sb_h_double_arrow\0"],
+            CursorShape::ResizeVertical => &[b"ns-resize\0", b"sb_v_double_arrow\0"],
+            CursorShape::ResizeNwSe => &[b"nwse-resize\0", b"bottom_right_corner\0"],
+            CursorShape::ResizeNeSw => &[b"nesw-resize\0", b"bottom_left_corner\0"],
+        }
+    }
+
+    fn font_shape(&self) -> c_uint
+    {
+        match *self {
+            CursorShape::Arrow => XC_LEFT_PTR,
+            CursorShape::Text => XC_XTERM,
+            CursorShape::Hand => XC_HAND2,
+            CursorShape::Crosshair => XC_CROSSHAIR,
+            CursorShape::Move => XC_FLEUR,
+            CursorShape::Wait => XC_WATCH,
+            CursorShape::Help => XC_QUESTION_ARROW,
+            CursorShape::NotAllowed => XC_X_CURSOR,
+            CursorShape::ResizeHorizontal => XC_SB_H_DOUBLE_ARROW,
+            CursorShape::ResizeVertical => XC_SB_V_DOUBLE_ARROW,
+            CursorShape::ResizeNwSe => XC_BOTTOM_RIGHT_CORNER,
+            CursorShape::ResizeNeSw => XC_BOTTOM_LEFT_CORNER,
+        }
+    }
+}
+
+// from the user's cursor theme, or the core cursor font if the theme doesn't have it
+pub unsafe fn load_shape(display: *mut xlib::Display, shape: CursorShape) -> xlib::Cursor
+{
+    for name in shape.theme_names()
+    {
+        let cursor = xcursor::XcursorLibraryLoadCursor(display, name.as_ptr() as *const c_char);
+        if cursor != 0
+        {
+            return cursor
+        }
+    }
+    xlib::XCreateFontCursor(display, shape.font_shape())
+}
+
+// 0 on failure
+pub unsafe fn create_rgba(display: *mut xlib::Display, width: u32, height: u32, rgba_data: &[u8],
+    hotspot: (u32, u32)) -> xlib::Cursor
+{
+    let image = xcursor::XcursorImageCreate(width as c_int, height as c_int);
+    if image.is_null()
+    {
+        return 0
+    }
+    (*image).xhot = hotspot.0.min(width.saturating_sub(1));
+    (*image).yhot = hotspot.1.min(height.saturating_sub(1));
+
+    // Xcursor wants premultiplied ARGB in native u32s
+    let pixels = slice::from_raw_parts_mut((*image).pixels, width as usize * height as usize);
+    for (dst, src) in pixels.iter_mut().zip(rgba_data.chunks_exact(4))
+    {
+        let alpha = src[3] as u32;
+        let premul = |c: u8| (c as u32 * alpha + 127) / 255;
+        *dst = alpha << 24 | premul(src[0]) << 16 | premul(src[1]) << 8 | premul(src[2]);
+    }
+
+    let cursor = xcursor::XcursorImageLoadCursor(display, image);
+    xcursor::XcursorImageDestroy(image);
+    cursor
+}
+
+// for hiding the cursor, a 1x1 cursor with an empty mask
+pub unsafe fn create_blank(display: *mut xlib::Display, drawable: xlib::Drawable) -> xlib::Cursor
+{
+    let data = [0u8];
+    let pixmap = xlib::XCreateBitmapFromData(display, drawable, data.as_ptr() as *const _, 1, 1);
+    let mut color: xlib::XColor = mem::zeroed();
+    let cursor = xlib::XCreatePixmapCursor(display, pixmap, pixmap, &mut color, &mut color, 0, 0);
+    xlib::XFreePixmap(display, pixmap);
+    cursor
+}
@@ -0,0 +1,108 @@
+use glengine;
+use event::{Key, HotkeyId};
+use x11::xlib;
+use std::os::raw::c_ulong;
+use std::fmt;
+use std::error;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error
+{
+    OpenDisplay,
+    MissingExtension(&'static str /* extension name */),
+    XRequest(&'static str /* failed request */),
+    UnmappedKey(Key),
+    HotkeyTaken(HotkeyId /* registered with the same keys */),
+    ImageSize(u32, u32, usize /* data length */),
+    Grab(GrabError),
+    Protocol(ProtocolError),
+    Disconnected,
+    Os(&'static str /* failed call */, i32 /* errno */),
+    Engine(glengine::Error),
+}
+
+// why the server refused a grab
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrabError
+{
+    AlreadyGrabbed,
+    InvalidTime,
+    NotViewable,
+    Frozen,
+}
+
+impl fmt::Display for GrabError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            GrabError::AlreadyGrabbed => write!(f, "already grabbed by another client"),
+            GrabError::InvalidTime => write!(f, "invalid time"),
+            GrabError::NotViewable => write!(f, "window not viewable"),
+            GrabError::Frozen => write!(f, "device frozen by another grab"),
+        }
+    }
+}
+
+// an error reply from the X server (BadWindow, BadMatch, etc)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolError
+{
+    pub error_code: u8,
+    pub request_code: u8,
+    pub minor_code: u8,
+    pub resource_id: xlib::XID,
+    pub serial: c_ulong,
+    pub request: String,
+    pub description: String,
+}
+
+impl fmt::Display for ProtocolError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} (error {}) in request {} ({}.{}), resource 0x{:x}, serial {}",
+            self.description, self.error_code, self.request, self.request_code, self.minor_code,
+            self.resource_id, self.serial)
+    }
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            Error::OpenDisplay => write!(f, "can't open X display"),
+            Error::MissingExtension(name) => write!(f, "X extension {} unavailable", name),
+            Error::XRequest(req) => write!(f, "X request {} failed", req),
+            Error::UnmappedKey(key) => write!(f, "no keycode produces {:?} on the current keymap", key),
+            Error::HotkeyTaken(id) => write!(f, "the same keys are already registered as hotkey {}", id.0),
+            Error::ImageSize(w, h, len) => write!(f, "{} bytes of image data don't match a {}x{} RGBA image", len, w, h),
+            Error::Grab(err) => write!(f, "grab failed: {}", err),
+            Error::Protocol(ref err) => write!(f, "X protocol error: {}", err),
+            Error::Disconnected => write!(f, "display disconnected"),
+            Error::Os(call, errno) => write!(f, "{} failed: {}", call, io::Error::from_raw_os_error(errno)),
+            Error::Engine(ref err) => write!(f, "draw engine: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)>
+    {
+        match *self {
+            Error::Engine(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<glengine::Error> for Error
+{
+    fn from(err: glengine::Error) -> Self
+    {
+        Error::Engine(err)
+    }
+}
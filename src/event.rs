@@ -1,16 +1,64 @@
+use std::time::Duration;
+use std::ops::{BitOr, BitOrAssign};
+
+// X server time in milliseconds, wraps around every ~49.7 days
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Timestamp(pub u32);
+
+impl Timestamp
+{
+    pub fn duration_since(&self, earlier: Timestamp) -> Duration
+    {
+        Duration::from_millis(self.0.wrapping_sub(earlier.0) as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HotkeyId(pub u64);
+
+// set on the key presses generated by auto repeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Repeat(pub bool);
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Event
+pub enum Event<U = ()>
 {
-    Keyboard(EvState, Key),
-    MouseButton(EvState, Button, (f64, f64)),
+    Keyboard(EvState, Key, ScanCode, Modifiers, Repeat),
+    Text(String),
+    PreeditStart,
+    PreeditUpdate(String, usize /* cursor, in chars */),
+    PreeditEnd,
+    MouseButton(EvState, Button, (f64, f64), Modifiers),
     MouseMoved(f64, f64),
+    MouseDelta(f64, f64),
     AxisMoved(AxisState),
+    Touch(TouchState, u32 /* touch id */, (f64, f64), f64 /* pressure */),
     PointerInside(bool),
     Resized(u32, u32),
     Moved(i32, i32),
     Focused(bool),
     Redraw,
     CloseButton,
+    Timer(TimerId),
+    User(U),
+}
+
+// events that don't belong to any window
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplayEvent<U = ()>
+{
+    DeviceAdded(i32 /* device_id */),
+    DeviceRemoved(i32 /* device_id */),
+    KeymapChanged,
+    LayoutChanged(u8 /* group */, String /* layout name */),
+    ScreenChanged(u32, u32),
+    // a monitor was plugged, unplugged or rearranged, the screen size might be the same
+    MonitorsChanged,
+    Hotkey(HotkeyId, EvState, Repeat),
+    User(U),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -29,6 +77,95 @@ pub enum EvState
     Released,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TouchState
+{
+    Begin,
+    Update,
+    End,
+}
+
+// set of modifiers held (or locked) when the event happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers
+{
+    pub const SHIFT: Modifiers = Modifiers(0x01);
+    pub const CTRL: Modifiers = Modifiers(0x02);
+    pub const ALT: Modifiers = Modifiers(0x04);
+    pub const SUPER: Modifiers = Modifiers(0x08);
+    pub const CAPS_LOCK: Modifiers = Modifiers(0x10);
+    pub const NUM_LOCK: Modifiers = Modifiers(0x20);
+
+    // stable names, in display order
+    pub const NAMES: [(Modifiers, &'static str); 6] = [
+        (Modifiers::CTRL, "Ctrl"),
+        (Modifiers::ALT, "Alt"),
+        (Modifiers::SHIFT, "Shift"),
+        (Modifiers::SUPER, "Super"),
+        (Modifiers::CAPS_LOCK, "CapsLock"),
+        (Modifiers::NUM_LOCK, "NumLock"),
+    ];
+
+    pub fn empty() -> Self
+    {
+        Modifiers(0)
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: Modifiers) -> bool
+    {
+        self.0 & other.0 == other.0
+    }
+
+    // ignores the lock modifiers, for matching shortcuts
+    pub fn without_locks(&self) -> Self
+    {
+        Modifiers(self.0 & !(Modifiers::CAPS_LOCK.0 | Modifiers::NUM_LOCK.0))
+    }
+}
+
+impl BitOr for Modifiers
+{
+    type Output = Modifiers;
+
+    fn bitor(self, other: Modifiers) -> Modifiers
+    {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Modifiers
+{
+    fn bitor_assign(&mut self, other: Modifiers)
+    {
+        self.0 |= other.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockKey
+{
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+// pointer position and held buttons, asked to the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointerState
+{
+    pub position: (f64, f64),
+    pub root_position: (f64, f64),
+    pub buttons: Vec<Button>,
+    pub mods: Modifiers,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AxisState
 {
@@ -37,9 +174,43 @@ pub enum AxisState
     Tilt(f64, f64),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Key
+// declares a key enum together with a table of stable names, used for serialization
+macro_rules! declare_keys
+{
+    ($name:ident, $($key:ident,)+) => (
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name
+        {
+            $($key,)+
+            Unk(u32)
+        }
+
+        impl $name
+        {
+            // None for Unk, those are stored by their raw value
+            pub fn name(&self) -> Option<&'static str>
+            {
+                match *self {
+                    $($name::$key => Some(stringify!($key)),)+
+                    $name::Unk(_) => None,
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$name>
+            {
+                match name {
+                    $(stringify!($key) => Some($name::$key),)+
+                    _ => None,
+                }
+            }
+        }
+    )
+}
+
+// keysym based, depends on the layout
+declare_keys!
 {
+    Key,
     // main numbers
     Key0,
     Key1,
@@ -91,6 +262,18 @@ pub enum Key
     F10,
     F11,
     F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
     // main control keys
     Escape,
     BackSpace,
@@ -108,6 +291,11 @@ pub enum Key
     MetaLeft,
     MetaRight,
     ModeSwitch,
+    Level5Shift,
+    NextGroup,
+    Compose,
+    Menu,
+    Help,
     Space,
     // secondary control keys
     Print,
@@ -137,12 +325,26 @@ pub enum Key
     Numpad9,
     // numpad other
     NumLock,
-    NumpadDelete,
+    NumpadDecimal,
+    NumpadSeparator,
+    NumpadEqual,
     NumpadAdd,
     NumpadSubtract,
     NumpadMultiply,
     NumpadDivide,
     NumpadEnter,
+    // numpad with NumLock off
+    NumpadInsert,
+    NumpadDelete,
+    NumpadHome,
+    NumpadEnd,
+    NumpadPageUp,
+    NumpadPageDown,
+    NumpadUp,
+    NumpadDown,
+    NumpadRight,
+    NumpadLeft,
+    NumpadBegin,
     // misc international
     Numbersign,
     Apostrophe,
@@ -178,6 +380,169 @@ pub enum Key
     DeadCircumflex,
     DeadTilde,
     DeadCedilla,
-    // there are lots more, but this is enough for most layouts
-    Unk(u32)
+    // media
+    VolumeMute,
+    VolumeDown,
+    VolumeUp,
+    MicMute,
+    MediaPlay,
+    MediaPause,
+    MediaStop,
+    MediaPrev,
+    MediaNext,
+    MediaRecord,
+    MediaRewind,
+    MediaForward,
+    MediaSelect,
+    // browser and launchers
+    BrowserBack,
+    BrowserForward,
+    BrowserRefresh,
+    BrowserStop,
+    BrowserSearch,
+    BrowserFavorites,
+    BrowserHome,
+    LaunchMail,
+    LaunchCalculator,
+    LaunchComputer,
+    // system
+    Eject,
+    Sleep,
+    WakeUp,
+    PowerOff,
+    BrightnessDown,
+    BrightnessUp,
+}
+
+// physical key position, the same on every layout. Unk has the X keycode
+declare_keys!
+{
+    ScanCode,
+    // main block, named after the key position on a US layout
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    Minus,
+    Equal,
+    BackSpace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    BracketLeft,
+    BracketRight,
+    Return,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Apostrophe,
+    Grave,
+    BackSlash,
+    IntlBackslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    IntlRo,
+    IntlYen,
+    Space,
+    // modifiers
+    CapsLock,
+    ShiftLeft,
+    ShiftRight,
+    ControlLeft,
+    ControlRight,
+    AltLeft,
+    AltRight,
+    SuperLeft,
+    SuperRight,
+    Menu,
+    // function keys
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    // secondary control keys
+    Print,
+    ScrollLock,
+    Pause,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    // arrow keys
+    Up,
+    Down,
+    Right,
+    Left,
+    // numpad
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    NumLock,
+    NumpadDecimal,
+    NumpadComma,
+    NumpadAdd,
+    NumpadSubtract,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadEqual,
+    NumpadEnter,
 }
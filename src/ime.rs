@@ -0,0 +1,404 @@
+// XIM input method and input contexts: committed text, and the preedit (composition) callbacks
+use x11::xlib;
+use libc;
+use std::ptr;
+use std::ffi::CStr;
+use std::cell::RefCell;
+use std::os::raw::{c_char, c_int, c_long, c_short, c_ulong, c_ushort, c_void};
+
+// from Xlib.h, not in x11-rs
+#[repr(C)]
+struct XIMCallback
+{
+    client_data: xlib::XPointer,
+    callback: *const c_void,
+}
+
+#[repr(C)]
+struct XIMStyles
+{
+    count_styles: c_ushort,
+    supported_styles: *mut c_ulong,
+}
+
+#[repr(C)]
+struct XIMText
+{
+    length: c_ushort,
+    feedback: *mut c_ulong,
+    encoding_is_wchar: xlib::Bool,
+    string: *mut c_char,
+}
+
+#[repr(C)]
+struct XIMPreeditDrawCallbackStruct
+{
+    caret: c_int,
+    chg_first: c_int,
+    chg_length: c_int,
+    text: *mut XIMText,
+}
+
+#[repr(C)]
+struct XIMPreeditCaretCallbackStruct
+{
+    position: c_int,
+    direction: c_int,
+    style: c_int,
+}
+
+// XIMCaretDirection
+const XIM_FORWARD_CHAR: c_int = 0;
+const XIM_BACKWARD_CHAR: c_int = 1;
+const XIM_LINE_START: c_int = 8;
+const XIM_LINE_END: c_int = 9;
+const XIM_ABSOLUTE_POSITION: c_int = 10;
+
+const STYLE_CALLBACKS: c_ulong = (xlib::XIMPreeditCallbacks | xlib::XIMStatusNothing) as c_ulong;
+const STYLE_NOTHING: c_ulong = (xlib::XIMPreeditNothing | xlib::XIMStatusNothing) as c_ulong;
+
+// composition text of the input method, kept up to date by the XIM preedit callbacks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preedit
+{
+    text: Vec<char>,
+    cursor: usize,
+}
+
+impl Preedit
+{
+    pub fn text(&self) -> String
+    {
+        self.text.iter().collect()
+    }
+
+    // in chars
+    pub fn cursor(&self) -> usize
+    {
+        self.cursor
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.text.len()
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    // replaces len chars at first with new_text, out of range values are clamped
+    pub fn replace(&mut self, first: usize, len: usize, new_text: &str)
+    {
+        let first = first.min(self.text.len());
+        let end = first.saturating_add(len).min(self.text.len());
+        self.text.splice(first .. end, new_text.chars());
+        self.cursor = self.cursor.min(self.text.len());
+    }
+
+    pub fn move_caret(&mut self, pos: usize)
+    {
+        self.cursor = pos.min(self.text.len());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreeditEvent
+{
+    Start,
+    Update(String, usize /* cursor */),
+    End,
+}
+
+// the callbacks get a pointer to this, the events are picked up after XFilterEvent
+#[derive(Debug, Default)]
+pub struct ImeState
+{
+    preedit: RefCell<Preedit>,
+    pending: RefCell<Vec<PreeditEvent>>,
+}
+
+impl ImeState
+{
+    pub fn take_events(&self) -> Vec<PreeditEvent>
+    {
+        self.pending.borrow_mut().drain(..).collect()
+    }
+
+    fn push_update(&self)
+    {
+        let ev = {
+            let preedit = self.preedit.borrow();
+            PreeditEvent::Update(preedit.text(), preedit.cursor())
+        };
+        self.pending.borrow_mut().push(ev);
+    }
+}
+
+unsafe extern "C" fn preedit_start(_ic: xlib::XIC, client_data: xlib::XPointer, _call_data: xlib::XPointer) -> c_int
+{
+    let state = &*(client_data as *const ImeState);
+    state.preedit.borrow_mut().clear();
+    state.pending.borrow_mut().push(PreeditEvent::Start);
+    -1  // no length limit
+}
+
+unsafe extern "C" fn preedit_done(_ic: xlib::XIC, client_data: xlib::XPointer, _call_data: xlib::XPointer)
+{
+    let state = &*(client_data as *const ImeState);
+    state.preedit.borrow_mut().clear();
+    state.pending.borrow_mut().push(PreeditEvent::End);
+}
+
+unsafe extern "C" fn preedit_draw(_ic: xlib::XIC, client_data: xlib::XPointer, call_data: xlib::XPointer)
+{
+    let state = &*(client_data as *const ImeState);
+    let call = &*(call_data as *const XIMPreeditDrawCallbackStruct);
+
+    // no text means the range was deleted, text without a string only changes the highlighting
+    let new_text = if call.text.is_null()
+    {
+        Some(String::new())
+    }
+    else
+    {
+        let text = &*call.text;
+        // we never ask for wchar, and the multibyte encoding is UTF-8 on any sane locale
+        if text.string.is_null() || text.encoding_is_wchar != 0 { None }
+        else { Some(CStr::from_ptr(text.string).to_string_lossy().into_owned()) }
+    };
+
+    {
+        let mut preedit = state.preedit.borrow_mut();
+        if let Some(text) = new_text
+        {
+            preedit.replace(call.chg_first.max(0) as usize, call.chg_length.max(0) as usize, &text);
+        }
+        preedit.move_caret(call.caret.max(0) as usize);
+    }
+    state.push_update();
+}
+
+unsafe extern "C" fn preedit_caret(_ic: xlib::XIC, client_data: xlib::XPointer, call_data: xlib::XPointer)
+{
+    let state = &*(client_data as *const ImeState);
+    let call = &mut *(call_data as *mut XIMPreeditCaretCallbackStruct);
+
+    {
+        let mut preedit = state.preedit.borrow_mut();
+        let cursor = preedit.cursor();
+        let pos = match call.direction {
+            XIM_ABSOLUTE_POSITION => call.position.max(0) as usize,
+            XIM_FORWARD_CHAR => cursor + 1,
+            XIM_BACKWARD_CHAR => cursor.saturating_sub(1),
+            XIM_LINE_START => 0,
+            XIM_LINE_END => preedit.len(),
+            _ => cursor,
+        };
+        preedit.move_caret(pos);
+        // the IM reads back where the caret ended
+        call.position = preedit.cursor() as c_int;
+    }
+    state.push_update();
+}
+
+// Xlib picks the input method from XMODIFIERS, and needs the locale set from the environment for that.
+// the locale is process wide, so it's only set if the application left the default "C" one
+pub unsafe fn open_im(display: *mut xlib::Display) -> xlib::XIM
+{
+    let current = libc::setlocale(libc::LC_CTYPE, ptr::null());
+    if current.is_null() || matches!(CStr::from_ptr(current).to_bytes(), b"C" | b"POSIX")
+    {
+        libc::setlocale(libc::LC_CTYPE, b"\0".as_ptr() as *const _);
+    }
+    if xlib::XSupportsLocale() == xlib::False
+    {
+        return ptr::null_mut()
+    }
+
+    xlib::XSetLocaleModifiers(b"\0".as_ptr() as *const _);
+    let xim = xlib::XOpenIM(display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+    if !xim.is_null()
+    {
+        return xim
+    }
+
+    // the configured IM isn't running, the builtin one still does compose sequences
+    xlib::XSetLocaleModifiers(b"@im=none\0".as_ptr() as *const _);
+    xlib::XOpenIM(display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
+}
+
+unsafe fn supports_style(xim: xlib::XIM, style: c_ulong) -> bool
+{
+    let mut styles: *mut XIMStyles = ptr::null_mut();
+    if !xlib::XGetIMValues(xim, b"queryInputStyle\0".as_ptr(), &mut styles, ptr::null::<u8>()).is_null() || styles.is_null()
+    {
+        return false
+    }
+    let found = (0 .. (*styles).count_styles as isize).any(|i| *(*styles).supported_styles.offset(i) == style);
+    xlib::XFree(styles as *mut _);
+    found
+}
+
+// the input context for a window, also adds the events the IM needs to the window mask.
+// state must stay at the same address until the IC is destroyed
+pub unsafe fn create_ic(display: *mut xlib::Display, xim: xlib::XIM, win: xlib::Window, event_mask: c_long,
+    state: *const ImeState) -> xlib::XIC
+{
+    if xim.is_null()
+    {
+        return ptr::null_mut()
+    }
+
+    // on-the-spot preedit if the IM can do it, otherwise it draws the composition in its own window
+    let ic = if supports_style(xim, STYLE_CALLBACKS)
+    {
+        let client_data = state as xlib::XPointer;
+        let start = XIMCallback{ client_data, callback: preedit_start as *const c_void };
+        let done = XIMCallback{ client_data, callback: preedit_done as *const c_void };
+        let draw = XIMCallback{ client_data, callback: preedit_draw as *const c_void };
+        let caret = XIMCallback{ client_data, callback: preedit_caret as *const c_void };
+        let spot = xlib::XPoint{ x: 0, y: 0 };
+        let preedit_attr = xlib::XVaCreateNestedList(0,
+            b"preeditStartCallback\0".as_ptr(), &start,
+            b"preeditDoneCallback\0".as_ptr(), &done,
+            b"preeditDrawCallback\0".as_ptr(), &draw,
+            b"preeditCaretCallback\0".as_ptr(), &caret,
+            b"spotLocation\0".as_ptr(), &spot,
+            ptr::null::<u8>());
+
+        let ic = xlib::XCreateIC(xim,
+            b"inputStyle\0".as_ptr(), STYLE_CALLBACKS,
+            b"clientWindow\0".as_ptr(), win,
+            b"focusWindow\0".as_ptr(), win,
+            b"preeditAttributes\0".as_ptr(), preedit_attr,
+            ptr::null::<u8>());
+        xlib::XFree(preedit_attr);
+        ic
+    }
+    else { ptr::null_mut() };
+
+    let ic = if ic.is_null()
+    {
+        xlib::XCreateIC(xim,
+            b"inputStyle\0".as_ptr(), STYLE_NOTHING,
+            b"clientWindow\0".as_ptr(), win,
+            b"focusWindow\0".as_ptr(), win,
+            ptr::null::<u8>())
+    }
+    else { ic };
+
+    if ic.is_null()
+    {
+        return ic
+    }
+
+    let mut filter_events: c_long = 0;
+    xlib::XGetICValues(ic, b"filterEvents\0".as_ptr(), &mut filter_events, ptr::null::<u8>());
+    xlib::XSelectInput(display, win, event_mask | filter_events);
+    ic
+}
+
+// where the IM should put its candidate window, in window coordinates
+pub fn set_spot(ic: xlib::XIC, x: i32, y: i32)
+{
+    if ic.is_null()
+    {
+        return
+    }
+
+    let spot = xlib::XPoint{ x: x as c_short, y: y as c_short };
+    unsafe
+    {
+        let preedit_attr = xlib::XVaCreateNestedList(0, b"spotLocation\0".as_ptr(), &spot, ptr::null::<u8>());
+        xlib::XSetICValues(ic, b"preeditAttributes\0".as_ptr(), preedit_attr, ptr::null::<u8>());
+        xlib::XFree(preedit_attr);
+    }
+}
+
+// characters typed, after the layout, modifiers and compose sequences are applied
+pub fn lookup_text(ic: xlib::XIC, ev: &mut xlib::XKeyEvent) -> Option<String>
+{
+    if ic.is_null()
+    {
+        return None
+    }
+
+    let mut buf = vec![0u8; 32];
+    let mut status = 0;
+    let mut len = unsafe { xlib::Xutf8LookupString(ic, ev, buf.as_mut_ptr() as *mut _, buf.len() as c_int, ptr::null_mut(), &mut status) };
+    if status == xlib::XBufferOverflow
+    {
+        buf.resize(len as usize, 0);
+        len = unsafe { xlib::Xutf8LookupString(ic, ev, buf.as_mut_ptr() as *mut _, buf.len() as c_int, ptr::null_mut(), &mut status) };
+    }
+
+    match status {
+        xlib::XLookupChars | xlib::XLookupBoth => {
+            buf.truncate(len as usize);
+            String::from_utf8(buf).ok()
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn preedit(text: &str, cursor: usize) -> Preedit
+    {
+        let mut preedit = Preedit::default();
+        preedit.replace(0, 0, text);
+        preedit.move_caret(cursor);
+        preedit
+    }
+
+    #[test]
+    fn insert_and_delete()
+    {
+        let mut p = preedit("abc", 3);
+        p.replace(1, 0, "\u{3042}\u{3044}");
+        assert_eq!(p.text(), "a\u{3042}\u{3044}bc");
+        assert_eq!(p.len(), 5);
+
+        p.replace(1, 2, "");
+        assert_eq!(p.text(), "abc");
+        // replacing a range with something else
+        p.replace(0, 2, "xyz");
+        assert_eq!(p.text(), "xyzc");
+        assert_eq!(p.cursor(), 3);
+    }
+
+    #[test]
+    fn out_of_range()
+    {
+        let mut p = preedit("abc", 1);
+        // appends when chg_first is past the end
+        p.replace(10, 5, "d");
+        assert_eq!(p.text(), "abcd");
+        // the length is clamped to the text
+        p.replace(2, usize::MAX, "");
+        assert_eq!(p.text(), "ab");
+        assert_eq!(p.cursor(), 1);
+    }
+
+    #[test]
+    fn caret()
+    {
+        let mut p = preedit("abcd", 4);
+        p.replace(1, 3, "");
+        // the caret stays inside the text
+        assert_eq!(p.cursor(), 1);
+        p.move_caret(100);
+        assert_eq!(p.cursor(), 1);
+        p.move_caret(0);
+        assert_eq!(p.cursor(), 0);
+
+        p.clear();
+        assert_eq!((p.text(), p.cursor(), p.len()), (String::new(), 0, 0));
+    }
+}
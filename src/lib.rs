@@ -1,203 +1,51 @@
 extern crate x11;
+extern crate libc;
 extern crate glengine;
 
 pub mod event;
-
-use x11::{xlib, xinput2, keysym};
+pub mod error;
+pub mod record;
+mod translate;
+pub mod shortcut;
+mod xerror;
+mod proxy;
+mod xkb;
+mod ime;
+mod cursor;
+
+use x11::{xlib, xinput2, xrandr};
 use std::{mem, slice};
 use std::ptr;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_uint};
 use std::collections::{hash_map, HashMap, VecDeque};
 use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::fs::File;
+use std::path::Path;
+use std::os::unix::io::{AsRawFd, RawFd};
+use shortcut::Shortcut;
 
 pub use event::*;
+pub use error::{Error, GrabError, ProtocolError};
+pub use proxy::{EventProxy, WindowId};
+pub use cursor::{CursorId, CursorShape};
 
-fn as_button(button: i32) -> Button
-{
-    match button {
-        1 => Button::Left,
-        2 => Button::Middle,
-        3 => Button::Right,
-        other => Button::Other(other as u8)
-    }
-}
-
-fn as_axis_scroll(button: i32) -> AxisState
-{
-    match button {
-        4 => AxisState::Scroll(0.0, 1.0),
-        5 => AxisState::Scroll(0.0, -1.0),
-        6 => AxisState::Scroll(-1.0, 0.0),
-        7 => AxisState::Scroll(1.0, 0.0),
-        _ => unreachable!()
-    }
-}
-
-fn convert_keysym(keysym: u32) -> Key
-{
-    match keysym {
-        keysym::XK_0 => Key::Key0,
-        keysym::XK_1 => Key::Key1,
-        keysym::XK_2 => Key::Key2,
-        keysym::XK_3 => Key::Key3,
-        keysym::XK_4 => Key::Key4,
-        keysym::XK_5 => Key::Key5,
-        keysym::XK_6 => Key::Key6,
-        keysym::XK_7 => Key::Key7,
-        keysym::XK_8 => Key::Key8,
-        keysym::XK_9 => Key::Key9,
-
-        keysym::XK_a => Key::A,
-        keysym::XK_b => Key::B,
-        keysym::XK_c => Key::C,
-        keysym::XK_d => Key::D,
-        keysym::XK_e => Key::E,
-        keysym::XK_f => Key::F,
-        keysym::XK_g => Key::G,
-        keysym::XK_h => Key::H,
-        keysym::XK_i => Key::I,
-        keysym::XK_j => Key::J,
-        keysym::XK_k => Key::K,
-        keysym::XK_l => Key::L,
-        keysym::XK_m => Key::M,
-        keysym::XK_n => Key::N,
-        keysym::XK_o => Key::O,
-        keysym::XK_p => Key::P,
-        keysym::XK_q => Key::Q,
-        keysym::XK_r => Key::R,
-        keysym::XK_s => Key::S,
-        keysym::XK_t => Key::T,
-        keysym::XK_u => Key::U,
-        keysym::XK_v => Key::V,
-        keysym::XK_w => Key::W,
-        keysym::XK_x => Key::X,
-        keysym::XK_y => Key::Y,
-        keysym::XK_z => Key::Z,
-
-        keysym::XK_F1 => Key::F1,
-        keysym::XK_F2 => Key::F2,
-        keysym::XK_F3 => Key::F3,
-        keysym::XK_F4 => Key::F4,
-        keysym::XK_F5 => Key::F5,
-        keysym::XK_F6 => Key::F6,
-        keysym::XK_F7 => Key::F7,
-        keysym::XK_F8 => Key::F8,
-        keysym::XK_F9 => Key::F9,
-        keysym::XK_F10 => Key::F10,
-        keysym::XK_F11 => Key::F11,
-        keysym::XK_F12 => Key::F12,
-
-        keysym::XK_Escape => Key::Escape,
-        keysym::XK_BackSpace => Key::BackSpace,
-        keysym::XK_Tab => Key::Tab,
-        keysym::XK_Return => Key::Return,
-        keysym::XK_Caps_Lock => Key::CapsLock,
-        keysym::XK_Shift_L => Key::ShiftLeft,
-        keysym::XK_Shift_R => Key::ShiftRight,
-        keysym::XK_Control_L => Key::ControlLeft,
-        keysym::XK_Control_R => Key::ControlRight,
-        keysym::XK_Alt_L => Key::AltLeft,
-        keysym::XK_Alt_R => Key::AltRight,  // US only
-        keysym::XK_Super_L => Key::SuperLeft,
-        keysym::XK_Super_R => Key::SuperRight,
-        keysym::XK_Meta_L => Key::MetaLeft, // Mac's ⌘ key
-        keysym::XK_Meta_R => Key::MetaRight,
-        keysym::XK_Mode_switch => Key::ModeSwitch,  // Mac's ⌥ key
-        keysym::XK_space => Key::Space,
-
-        keysym::XK_Print => Key::Print,
-        keysym::XK_Scroll_Lock => Key::ScrollLock,
-        keysym::XK_Pause => Key::Pause,
-        keysym::XK_Insert => Key::Insert,
-        keysym::XK_Delete => Key::Delete,
-        keysym::XK_Home => Key::Home,
-        keysym::XK_End => Key::End,
-        keysym::XK_Page_Up => Key::PageUp,
-        keysym::XK_Page_Down => Key::PageDown,
-        keysym::XK_Up => Key::Up,
-        keysym::XK_Down => Key::Down,
-        keysym::XK_Right => Key::Right,
-        keysym::XK_Left => Key::Left,
-        // on my system it doesn't send numpad numbers here..
-        keysym::XK_KP_0 => Key::Numpad0,
-        keysym::XK_KP_1 => Key::Numpad1,
-        keysym::XK_KP_2 => Key::Numpad2,
-        keysym::XK_KP_3 => Key::Numpad3,
-        keysym::XK_KP_4 => Key::Numpad4,
-        keysym::XK_KP_5 => Key::Numpad5,
-        keysym::XK_KP_6 => Key::Numpad6,
-        keysym::XK_KP_7 => Key::Numpad7,
-        keysym::XK_KP_8 => Key::Numpad8,
-        keysym::XK_KP_9 => Key::Numpad9,
-        // .. but it does here
-        keysym::XK_KP_Insert => Key::Numpad0,
-        keysym::XK_KP_End => Key::Numpad1,
-        keysym::XK_KP_Down => Key::Numpad2,
-        keysym::XK_KP_Page_Down => Key::Numpad3,
-        keysym::XK_KP_Left => Key::Numpad4,
-        keysym::XK_KP_Begin => Key::Numpad5,
-        keysym::XK_KP_Right => Key::Numpad6,
-        keysym::XK_KP_Home => Key::Numpad7,
-        keysym::XK_KP_Up => Key::Numpad8,
-        keysym::XK_KP_Page_Up => Key::Numpad9,
-
-        keysym::XK_Num_Lock => Key::NumLock,
-        keysym::XK_KP_Delete => Key::NumpadDelete,
-        keysym::XK_KP_Add => Key::NumpadAdd,
-        keysym::XK_KP_Subtract => Key::NumpadSubtract,
-        keysym::XK_KP_Multiply => Key::NumpadMultiply,
-        keysym::XK_KP_Divide => Key::NumpadDivide,
-        keysym::XK_KP_Enter => Key::NumpadEnter,
-
-        keysym::XK_numbersign => Key::Numbersign,
-        keysym::XK_apostrophe => Key::Apostrophe,
-        keysym::XK_plus => Key::Plus,
-        keysym::XK_comma => Key::Comma,
-        keysym::XK_minus => Key::Minus,
-        keysym::XK_period => Key::Period,
-        keysym::XK_slash => Key::Slash,
-        keysym::XK_semicolon => Key::Semicolon,
-        keysym::XK_less => Key::Less,
-        keysym::XK_equal => Key::Equal,
-        keysym::XK_bracketleft => Key::BracketLeft,
-        keysym::XK_backslash => Key::BackSlash,
-        keysym::XK_bracketright => Key::BracketRight,
-        keysym::XK_grave => Key::Grave,
-        keysym::XK_braceleft => Key::BraceLeft,
-        keysym::XK_bar => Key::Bar,
-        keysym::XK_braceright => Key::BraceRight,
-        keysym::XK_exclamdown => Key::ExclamDown,
-        keysym::XK_guillemotleft => Key::GuillemotLeft,
-        keysym::XK_masculine => Key::Masculine,
-        keysym::XK_questiondown => Key::QuestionDown,
-        keysym::XK_agrave => Key::AGrave,
-        keysym::XK_ccedilla => Key::CCedilla,
-        keysym::XK_egrave => Key::EGrave,
-        keysym::XK_eacute => Key::EAcute,
-        keysym::XK_igrave => Key::IGrave,
-        keysym::XK_ntilde => Key::NTilde,
-        keysym::XK_ograve => Key::OGrave,
-        keysym::XK_ugrave => Key::UGrave,
-        // things not in x11::keysym
-        0xfe03 /* XK_ISO_Level3_Shift */ => Key::AltRight,  // AltGr -- Non-US sends this instead of Alt_R
-        0xfe50 /* XK_dead_grave */ => Key::DeadGrave,
-        0xfe51 /* XK_dead_acute */ => Key::DeadAcute,
-        0xfe52 /* XK_dead_circumflex */ => Key::DeadCircumflex,
-        0xfe53 /* XK_dead_tilde */ => Key::DeadTilde,
-        0xfe5b /* XK_dead_cedilla */ => Key::DeadCedilla,
-        other => Key::Unk(other)
-    }
-}
+// XEventsQueued mode from Xlib.h, not in x11-rs
+const QUEUED_ALREADY: c_int = 0;
 
-enum ParsedEvent
+enum ParsedEvent<U>
 {
     None,
-    One(Event),
-    Many(Vec<Event>),
+    One(Event<U>),
+    Many(Vec<Event<U>>),
+    Display(Vec<DisplayEvent<U>>),
 }
 
-impl Default for ParsedEvent
+impl<U> Default for ParsedEvent<U>
 {
     fn default() -> Self
     {
@@ -205,29 +53,21 @@ impl Default for ParsedEvent
     }
 }
 
-#[derive(Debug)]
-enum AxisType
+struct Timer
 {
-    ScrollVertical(f64 /* increment */),
-    ScrollHorizontal(f64 /* increment */),
-    Pressure(f64 /* max */),
-    TiltX(f64 /* max */),
-    TiltY(f64 /* max */),
+    id: TimerId,
+    win: xlib::Window,
+    deadline: Instant,
+    period: Option<Duration>,
 }
 
-#[derive(Debug)]
-struct AxisData
+struct Hotkey
 {
-    axis_type: AxisType,
-    value: f64,
-}
-
-#[derive(Debug, Default)]
-struct DeviceInfo
-{
-    axis_info: HashMap<i32 /* axis_num */, AxisData>,
-    num_axis: i32,
-    has_scroll: bool,
+    id: HotkeyId,
+    shortcut: Shortcut,
+    keycode: xlib::KeyCode,
+    states: [c_uint; 4],
+    down: bool,
 }
 
 #[repr(C)]
@@ -238,50 +78,120 @@ struct AtomCache
     abs_pressure: xlib::Atom,
     abs_tilt_x: xlib::Atom,
     abs_tilt_y: xlib::Atom,
+    caps_lock: xlib::Atom,
+    num_lock: xlib::Atom,
+    scroll_lock: xlib::Atom,
+    abs_mt_pressure: xlib::Atom,
 }
 
-pub struct XDisplay
+pub struct XDisplay<U = ()>
 {
     handle: *mut xlib::Display,
-    win_data: RefCell<HashMap<xlib::Window, Weak<WindowData>>>,
-    devices: RefCell<HashMap<i32 /* device_id */, DeviceInfo>>,
-    pointer_pos: Cell<(f64, f64)>,
+    win_data: RefCell<HashMap<xlib::Window, Weak<WindowData<U>>>>,
+    input: RefCell<translate::InputState>,
+    mod_map: Cell<translate::ModifierMap>,
+    key_groups: RefCell<Vec<u8> /* group_info by keycode */>,
+    xkb_event: c_int,
+    has_touch: bool,
+    group: Cell<u8>,
+    group_names: RefCell<Vec<String>>,
+    ev_queue: RefCell<VecDeque<(DisplayEvent<U>, Timestamp)>>,
+    timers: RefCell<Vec<Timer>>,
+    next_timer_id: Cell<u64>,
+    hotkeys: RefCell<Vec<Hotkey>>,
+    next_hotkey_id: Cell<u64>,
+    pointer_lock: Cell<xlib::Window>,
+    pointer_grab: Cell<i32 /* device_id */>,
+    blank_cursor: Cell<xlib::Cursor>,
+    shape_cursors: RefCell<HashMap<CursorShape, xlib::Cursor>>,
+    proxy: Arc<proxy::ProxyShared<U>>,
+    last_time: Cell<Timestamp>,
+    time_ref: Cell<Option<(Timestamp, Instant)>>,
+    root_win: xlib::Window,
+    rr_event: c_int,
+    monitors_changed: Cell<bool /* already queued in this dispatch */>,
+    xim: xlib::XIM,
     atoms: AtomCache,
     engine: glengine::DrawEngine,
 }
 
 impl XDisplay
 {
-    pub fn new() -> Result<Self, &'static str>  //TODO: Err type
+    pub fn new() -> Result<Self, Error>
+    {
+        XDisplay::with_user_events()
+    }
+}
+
+impl<U> XDisplay<U>
+{
+    // U is the payload type of Event::User, sent through an EventProxy
+    pub fn with_user_events() -> Result<Self, Error>
     {
         // open display
         let display = unsafe{ xlib::XOpenDisplay(ptr::null()) };
         if display.is_null()
         {
-            return Err("Can't open display")
+            return Err(Error::OpenDisplay)
         }
 
+        // report X errors to us instead of killing the process
+        xerror::register(display);
+        let mut guard = ConnectionGuard{ handle: display, xim: ptr::null_mut() };
+
+        // input method for Event::Text, can be null if the locale isn't supported by Xlib
+        let xim = unsafe { ime::open_im(display) };
+        guard.xim = xim;
+
         // this initializes EGL and the GL context
         let engine = glengine::DrawEngine::new(display as _)?;
+        let proxy = proxy::ProxyShared::new()?;
 
         let mut xdis = XDisplay{
             handle: display,
             win_data: Default::default(),
-            devices: Default::default(),
-            pointer_pos: Cell::new((-1.0, -1.0)),
+            input: RefCell::new(translate::InputState::new()),
+            mod_map: Default::default(),
+            key_groups: Default::default(),
+            xkb_event: 0,
+            has_touch: false,
+            group: Cell::new(0),
+            group_names: Default::default(),
+            ev_queue: Default::default(),
+            timers: Default::default(),
+            next_timer_id: Cell::new(1),
+            hotkeys: Default::default(),
+            next_hotkey_id: Cell::new(1),
+            pointer_lock: Cell::new(0),
+            pointer_grab: Cell::new(0),
+            blank_cursor: Cell::new(0),
+            shape_cursors: Default::default(),
+            proxy,
+            last_time: Default::default(),
+            time_ref: Cell::new(None),
+            root_win: unsafe { xlib::XDefaultRootWindow(display) },
+            rr_event: 0,
+            monitors_changed: Cell::new(false),
+            xim,
             atoms: unsafe { mem::zeroed() },
             engine: engine,
         };
+        // from here on XDisplay's Drop closes the connection
+        mem::forget(guard);
 
         // get atoms
         let mut atom_names = [b"WM_DELETE_WINDOW\0".as_ptr() as *mut _,
                               b"WM_PROTOCOLS\0".as_ptr() as *mut _,
                               b"Abs Pressure\0".as_ptr() as *mut _,
                               b"Abs Tilt X\0".as_ptr() as *mut _,
-                              b"Abs Tilt Y\0".as_ptr() as *mut _];
+                              b"Abs Tilt Y\0".as_ptr() as *mut _,
+                              b"Caps Lock\0".as_ptr() as *mut _,
+                              b"Num Lock\0".as_ptr() as *mut _,
+                              b"Scroll Lock\0".as_ptr() as *mut _,
+                              b"Abs MT Pressure\0".as_ptr() as *mut _];
         if unsafe { xlib::XInternAtoms(display, &mut atom_names[0], atom_names.len() as i32, xlib::False, &mut xdis.atoms as *mut AtomCache as *mut _) } == 0
         {
-            return Err("Can't intern atoms")
+            return Err(Error::XRequest("XInternAtoms"))
         }
 
         // query for XInput support
@@ -291,7 +201,7 @@ impl XDisplay
         if unsafe { xlib::XQueryExtension(display, b"XInputExtension\0".as_ptr() as *const _,
             &mut ex_opcode, &mut ex_event, &mut ex_error) } == xlib::False
         {
-            return Err("XInput extension unavailable")
+            return Err(Error::MissingExtension("XInputExtension"))
         }
 
         // check XInput version
@@ -299,8 +209,10 @@ impl XDisplay
         let mut xi_minor = xinput2::XI_2_Minor;
         if unsafe { xinput2::XIQueryVersion(display, &mut xi_major, &mut xi_minor) } != xlib::Success as i32
         {
-            return Err("XInput2 not available")
+            return Err(Error::XRequest("XIQueryVersion"))
         }
+        // touch events need XI 2.2
+        xdis.has_touch = (xi_major, xi_minor) >= (2, 2);
 
         // enable XInput hierarchy events
         let mut mask = [0; 2];
@@ -312,10 +224,36 @@ impl XDisplay
             mask: mask.as_mut_ptr(),
         };
 
-        let root_win = unsafe { xlib::XDefaultRootWindow(display) };
-        if unsafe { xinput2::XISelectEvents(display, root_win, &mut event_mask, 1) } != xlib::Success as i32
+        if unsafe { xinput2::XISelectEvents(display, xdis.root_win, &mut event_mask, 1) } != xlib::Success as i32
+        {
+            return Err(Error::XRequest("XISelectEvents"))
+        }
+
+        // root window ConfigureNotify tells us when the screen size changes
+        unsafe { xlib::XSelectInput(display, xdis.root_win, xlib::StructureNotifyMask); }
+
+        // RandR tells us about monitor changes, without it we only see the screen size
+        let (mut rr_event, mut rr_error) = (0, 0);
+        if unsafe { xrandr::XRRQueryExtension(display, &mut rr_event, &mut rr_error) } != xlib::False
+        {
+            xdis.rr_event = rr_event;
+            let rr_mask = xrandr::RRScreenChangeNotifyMask | xrandr::RRCrtcChangeNotifyMask | xrandr::RROutputChangeNotifyMask;
+            unsafe { xrandr::XRRSelectInput(display, xdis.root_win, rr_mask) };
+        }
+
+        // XKB tells us when the keymap or the active layout changes
+        let (mut xkb_opcode, mut xkb_error) = (0, 0);
+        let (mut xkb_major, mut xkb_minor) = (1, 0);
+        if unsafe { xlib::XkbQueryExtension(display, &mut xkb_opcode, &mut xdis.xkb_event, &mut xkb_error, &mut xkb_major, &mut xkb_minor) } == xlib::False
+        {
+            return Err(Error::MissingExtension("XKEYBOARD"))
+        }
+        let xkb_events = (xlib::XkbNewKeyboardNotifyMask | xlib::XkbMapNotifyMask) as c_uint;
+        unsafe
         {
-            return Err("Failed to select HierarchyChanged event")
+            xlib::XkbSelectEvents(display, xkb::USE_CORE_KBD, xkb_events, xkb_events);
+            xlib::XkbSelectEventDetails(display, xkb::USE_CORE_KBD, xlib::XkbStateNotify as c_uint, xkb::GROUP_STATE_MASK, xkb::GROUP_STATE_MASK);
+            xlib::XkbSelectEventDetails(display, xkb::USE_CORE_KBD, xlib::XkbNamesNotify as c_uint, xkb::GROUP_NAMES_MASK, xkb::GROUP_NAMES_MASK);
         }
 
         // disable fake KeyRelease events on auto repeat
@@ -323,11 +261,224 @@ impl XDisplay
 
         // load device info
         xdis.load_axis_info(xinput2::XIAllDevices);
+        xdis.load_modifier_map();
+        xdis.load_key_groups();
+        xdis.load_layouts();
 
         Ok(xdis)
     }
 
-    pub fn create_window(&self, width: u32, height: u32) -> Result<XWindow, &'static str>
+    // delay before a held key starts repeating, and the time between repeats
+    pub fn get_key_repeat(&self) -> Result<(Duration, Duration), Error>
+    {
+        let (mut delay, mut interval) = (0, 0);
+        if unsafe { xlib::XkbGetAutoRepeatRate(self.handle, xkb::USE_CORE_KBD, &mut delay, &mut interval) } == xlib::False
+        {
+            return Err(Error::XRequest("XkbGetAutoRepeatRate"))
+        }
+        Ok((Duration::from_millis(delay as u64), Duration::from_millis(interval as u64)))
+    }
+
+    // changes the repeat timing of the core keyboard, this affects every client on the server
+    pub fn set_key_repeat(&self, delay: Duration, interval: Duration) -> Result<(), Error>
+    {
+        let ms = |d: Duration| d.as_millis().min(u16::MAX as u128) as c_uint;
+        let ok = self.checked("XkbSetAutoRepeatRate", || unsafe {
+            xlib::XkbSetAutoRepeatRate(self.handle, xkb::USE_CORE_KBD, ms(delay), ms(interval))
+        })?;
+        if ok == xlib::False
+        {
+            return Err(Error::XRequest("XkbSetAutoRepeatRate"))
+        }
+        Ok(())
+    }
+
+    // keys held right now, asks the server. useful to resync after focus changes, since
+    // keys released while the window wasn't focused don't send events
+    pub fn keys_down(&self) -> Vec<(Key, ScanCode)>
+    {
+        let bits = self.query_keymap();
+        let mut state = xkb::StateRec::default();
+        unsafe { xkb::XkbGetState(self.handle, xkb::USE_CORE_KBD, &mut state) };
+        let mods = self.mod_map.get().convert(state.locked_mods as c_uint);
+
+        translate::keymap_keycodes(&bits).into_iter()
+            .map(|kc| (translate::keycode_to_key(self, kc, state.group, mods), translate::convert_keycode(kc)))
+            .collect()
+    }
+
+    fn query_keymap(&self) -> [u8; 32]
+    {
+        let mut bits = [0u8; 32];
+        unsafe { xlib::XQueryKeymap(self.handle, bits.as_mut_ptr() as *mut _) };
+        bits
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool
+    {
+        self.keys_down().iter().any(|&(k, _)| k == key)
+    }
+
+    pub fn is_scancode_down(&self, scancode: ScanCode) -> bool
+    {
+        self.keys_down().iter().any(|&(_, sc)| sc == scancode)
+    }
+
+    fn lock_atom(&self, lock: LockKey) -> xlib::Atom
+    {
+        match lock {
+            LockKey::CapsLock => self.atoms.caps_lock,
+            LockKey::NumLock => self.atoms.num_lock,
+            LockKey::ScrollLock => self.atoms.scroll_lock,
+        }
+    }
+
+    // reads the state of the lock indicator
+    pub fn get_lock_state(&self, lock: LockKey) -> Result<bool, Error>
+    {
+        let mut state = 0;
+        if unsafe { xlib::XkbGetNamedIndicator(self.handle, self.lock_atom(lock), ptr::null_mut(), &mut state,
+            ptr::null_mut(), ptr::null_mut()) } == xlib::False
+        {
+            return Err(Error::XRequest("XkbGetNamedIndicator"))
+        }
+        Ok(state != 0)
+    }
+
+    // turns the lock LED on or off. whether the lock itself follows depends on the keymap
+    pub fn set_lock_led(&self, lock: LockKey, on: bool) -> Result<(), Error>
+    {
+        let ok = self.checked("XkbSetNamedIndicator", || unsafe {
+            xlib::XkbSetNamedIndicator(self.handle, self.lock_atom(lock), xlib::True, on as c_int, xlib::False, ptr::null_mut())
+        })?;
+        if ok == xlib::False
+        {
+            return Err(Error::XRequest("XkbSetNamedIndicator"))
+        }
+        Ok(())
+    }
+
+    // grabs the shortcut on the whole screen, presses arrive as DisplayEvent::Hotkey even when no window
+    // of ours has the focus. fails with a BadAccess protocol error if another client has it grabbed,
+    // and with HotkeyTaken if we registered the same keys already
+    pub fn register_hotkey(&self, shortcut: Shortcut) -> Result<HotkeyId, Error>
+    {
+        let keycode = self.hotkey_keycode(shortcut.key).ok_or(Error::UnmappedKey(shortcut.key))?;
+        let states = self.hotkey_states(shortcut.mods);
+        // X grabs are per client, a second one on the same keys would share the first
+        if let Some(hk) = self.hotkeys.borrow().iter().find(|hk| hk.keycode == keycode && hk.states == states)
+        {
+            return Err(Error::HotkeyTaken(hk.id))
+        }
+        if let Err(err) = self.checked("XGrabKey", || self.grab_hotkey(keycode, &states))
+        {
+            // some of the lock variants might have succeeded
+            self.ungrab_hotkey(keycode, &states);
+            return Err(err)
+        }
+
+        let id = HotkeyId(self.next_hotkey_id.get());
+        self.next_hotkey_id.set(id.0 + 1);
+        self.hotkeys.borrow_mut().push(Hotkey{ id, shortcut, keycode, states, down: false });
+        Ok(id)
+    }
+
+    // returns false if the hotkey wasn't registered
+    pub fn unregister_hotkey(&self, id: HotkeyId) -> bool
+    {
+        let mut hotkeys = self.hotkeys.borrow_mut();
+        match hotkeys.iter().position(|hk| hk.id == id) {
+            Some(i) => {
+                let hk = hotkeys.remove(i);
+                // a keymap change can leave two hotkeys on the same keys, keep the grab for the other
+                if !hotkeys.iter().any(|other| other.keycode == hk.keycode && other.states == hk.states)
+                {
+                    self.ungrab_hotkey(hk.keycode, &hk.states);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn hotkey_keycode(&self, key: Key) -> Option<xlib::KeyCode>
+    {
+        let keysym = shortcut::key_to_keysym(key)?;
+        match unsafe { xlib::XKeysymToKeycode(self.handle, keysym as xlib::KeySym) } {
+            0 => None,
+            keycode => Some(keycode),
+        }
+    }
+
+    // the grab only matches the exact modifier state, so it also needs every combination of the locks
+    fn hotkey_states(&self, mods: Modifiers) -> [c_uint; 4]
+    {
+        let mod_map = self.mod_map.get();
+        let state = mod_map.to_state(mods);
+        let num_lock = mod_map.num_lock;
+        [state, state | xlib::LockMask, state | num_lock, state | xlib::LockMask | num_lock]
+    }
+
+    fn grab_hotkey(&self, keycode: xlib::KeyCode, states: &[c_uint])
+    {
+        for &state in states
+        {
+            // owner_events is off, so the press goes to the root window even if one of ours has the focus
+            unsafe { xlib::XGrabKey(self.handle, keycode as c_int, state, self.root_win, xlib::False,
+                xlib::GrabModeAsync, xlib::GrabModeAsync) };
+        }
+    }
+
+    fn ungrab_hotkey(&self, keycode: xlib::KeyCode, states: &[c_uint])
+    {
+        // not grabbed, and 0 is AnyKey
+        if keycode == 0 { return }
+        for &state in states
+        {
+            unsafe { xlib::XUngrabKey(self.handle, keycode as c_int, state, self.root_win) };
+        }
+    }
+
+    // the keycodes and modifier bits can move when the keymap changes.
+    // errors are ignored here, a hotkey that can't be grabbed again just stops firing
+    fn regrab_hotkeys(&self)
+    {
+        for hk in self.hotkeys.borrow_mut().iter_mut()
+        {
+            self.ungrab_hotkey(hk.keycode, &hk.states);
+            match self.hotkey_keycode(hk.shortcut.key) {
+                Some(keycode) => {
+                    hk.keycode = keycode;
+                    hk.states = self.hotkey_states(hk.shortcut.mods);
+                    let _ = self.checked("XGrabKey", || self.grab_hotkey(hk.keycode, &hk.states));
+                },
+                // the key is gone from the new keymap, inactive until it comes back
+                None => hk.keycode = 0,
+            }
+        }
+    }
+
+    fn hotkey_events(&self, ev: &xlib::XKeyEvent, state: EvState) -> Vec<DisplayEvent<U>>
+    {
+        let keycode = ev.keycode as xlib::KeyCode;
+        let mods = self.mod_map.get().convert(ev.state);
+        let mut hotkeys = self.hotkeys.borrow_mut();
+        let hk = match state {
+            EvState::Pressed => hotkeys.iter_mut().find(|hk| hk.keycode == keycode && hk.shortcut.matches(hk.shortcut.key, mods)),
+            // the modifiers could be released first, so go by the key that was pressed
+            EvState::Released => hotkeys.iter_mut().find(|hk| hk.keycode == keycode && hk.down),
+        };
+        match hk {
+            Some(hk) => {
+                let repeat = state == EvState::Pressed && hk.down;
+                hk.down = state == EvState::Pressed;
+                vec![DisplayEvent::Hotkey(hk.id, state, Repeat(repeat))]
+            },
+            None => vec![],
+        }
+    }
+
+    pub fn create_window(&self, width: u32, height: u32) -> Result<XWindow<U>, Error>
     {
         XWindow::new(self, width, height)
     }
@@ -337,48 +488,405 @@ impl XDisplay
         glengine::Texture::new(&self.engine, width, height)
     }
 
-    pub fn wait_event(&self)
+    pub fn wait_event(&self) -> Result<(), Error>
     {
-        let mut xevent = unsafe { mem::zeroed() };
+        // we never block inside Xlib, so a lost connection can't hang or abort us
+        while !self.dispatch_pending()?
+        {
+            self.wait_readable(None);
+        }
+        Ok(())
+    }
+
+    // processes all the events already received without blocking, returns true if any window got events
+    pub fn poll_events(&self) -> Result<bool, Error>
+    {
+        self.dispatch_pending()
+    }
+
+    // entry point for external event loops: call it before every poll on the fd from as_raw_fd(),
+    // not only when the fd becomes readable. XPending flushes our requests and reads everything available,
+    // so right after this returns Xlib has nothing queued. but calls that wait for a server reply (query_pointer,
+    // keys_down, get_lock_state, grabs, ...) read the events that arrive meanwhile into Xlib's queue, and those
+    // don't make the fd readable again. has_queued_events() tells if that happened.
+    // X errors caused by earlier requests are returned here, after the events were stored
+    pub fn dispatch_pending(&self) -> Result<bool, Error>
+    {
+        self.monitors_changed.set(false);
+        let mut got_event = self.dispatch_user_events();
+        got_event |= self.dispatch_timers();
+        got_event |= self.dispatch_replays();
+        while unsafe { xlib::XPending(self.handle) } > 0
+        {
+            got_event |= self.next_event();
+        }
+        self.check_errors()?;
+        Ok(got_event)
+    }
+
+    // true if Xlib has events queued that dispatch_pending would process, without reading from the connection.
+    // an external loop must not sleep on the fd while this is true
+    pub fn has_queued_events(&self) -> bool
+    {
+        unsafe { xlib::XEventsQueued(self.handle, QUEUED_ALREADY) > 0 }
+    }
+
+    // like wait_event, but gives up after the timeout. returns false if it timed out.
+    // a timeout too large to represent (like Duration::MAX) waits forever
+    pub fn wait_event_timeout(&self, timeout: Duration) -> Result<bool, Error>
+    {
+        let deadline = Instant::now().checked_add(timeout);
         loop
         {
-            unsafe{ xlib::XNextEvent(self.handle, &mut xevent); }
+            if self.dispatch_pending()? { return Ok(true) }
 
-            let (win, parse_res) = self.parse_event(xevent);
+            if deadline.is_some_and(|d| Instant::now() >= d) { return Ok(false) }
 
-            let got_event = self.with_windata(win, move |wd| {
-                match parse_res {
-                    ParsedEvent::One(event) => {
-                        wd.ev_queue.borrow_mut().push_back(event);
-                        true
-                    },
-                    ParsedEvent::Many(events) => {
-                        wd.ev_queue.borrow_mut().extend(events);
-                        true
-                    },
-                    ParsedEvent::None => false,
-                }
+            self.wait_readable(deadline);
+        }
+    }
+
+    // sends Event::Timer(id) to the window once, after the delay
+    pub fn add_timer(&self, win: WindowId, delay: Duration) -> TimerId
+    {
+        self.insert_timer(win, delay, None)
+    }
+
+    // sends Event::Timer(id) to the window every period, until cancelled.
+    // periods under 1 ms are clamped to 1 ms, a zero period would fire on every dispatch
+    pub fn add_repeating_timer(&self, win: WindowId, period: Duration) -> TimerId
+    {
+        let period = period.max(Duration::from_millis(1));
+        self.insert_timer(win, period, Some(period))
+    }
+
+    // returns false if the timer already fired (one-shot) or doesn't exist
+    pub fn cancel_timer(&self, id: TimerId) -> bool
+    {
+        let mut timers = self.timers.borrow_mut();
+        let len = timers.len();
+        timers.retain(|t| t.id != id);
+        timers.len() != len
+    }
+
+    // for external event loops: dispatch_pending should be called again by this time (timers and replays)
+    pub fn next_deadline(&self) -> Option<Instant>
+    {
+        let next_timer = self.timers.borrow().iter().map(|t| t.deadline).min();
+        let next_replay = self.win_data.borrow().values()
+            .filter_map(|wd| wd.upgrade())
+            .filter_map(|wd| wd.replay.borrow().front().map(|r| r.0))
+            .min();
+        match (next_timer, next_replay) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn insert_timer(&self, win: WindowId, delay: Duration, period: Option<Duration>) -> TimerId
+    {
+        let id = TimerId(self.next_timer_id.get());
+        self.next_timer_id.set(id.0 + 1);
+        // a deadline past what Instant can hold would never fire anyway
+        if let Some(deadline) = Instant::now().checked_add(delay)
+        {
+            self.timers.borrow_mut().push(Timer{ id, win: win.0, deadline, period });
+        }
+        id
+    }
+
+    // sends the events for the expired timers
+    fn dispatch_timers(&self) -> bool
+    {
+        let now = Instant::now();
+        let time = self.last_time.get();
+        let mut expired = Vec::new();
+
+        self.timers.borrow_mut().retain_mut(|t| {
+            if t.deadline > now { return true }
+            expired.push((t.id, t.win));
+            match t.period {
+                Some(period) => {
+                    // if we fell behind, skip the missed ticks instead of firing them all at once
+                    let next = t.deadline.checked_add(period).filter(|&d| d > now).or_else(|| now.checked_add(period));
+                    match next {
+                        Some(deadline) => { t.deadline = deadline; true },
+                        None => false,
+                    }
+                },
+                None => false,
+            }
+        });
+
+        let mut got_event = false;
+        for (id, win) in expired
+        {
+            let delivered = self.with_windata(win, |wd| {
+                wd.ev_queue.borrow_mut().push_back((Event::Timer(id), time));
+                true
             });
+            // the window is gone, the timer can't fire anymore
+            if !delivered { self.cancel_timer(id); }
+            got_event |= delivered;
+        }
+        got_event
+    }
+
+    // a handle other threads can use to post Event::User and wake us up
+    pub fn create_proxy(&self) -> EventProxy<U>
+    {
+        EventProxy::new(self.proxy.clone())
+    }
+
+    // external event loops need to watch this too, and call dispatch_pending when it's readable
+    pub fn wakeup_fd(&self) -> RawFd
+    {
+        self.proxy.read_fd()
+    }
+
+    // moves the events posted by proxies into their queues
+    fn dispatch_user_events(&self) -> bool
+    {
+        let time = self.last_time.get();
+        let mut got_event = false;
+        for (target, payload) in self.proxy.take_events()
+        {
+            got_event |= match target {
+                Some(win) => self.with_windata(win, |wd| {
+                    wd.ev_queue.borrow_mut().push_back((Event::User(payload), time));
+                    true
+                }),
+                None => {
+                    self.ev_queue.borrow_mut().push_back((DisplayEvent::User(payload), time));
+                    true
+                },
+            };
+        }
+        got_event
+    }
+
+    // moves the replayed events that are due into their window queues
+    fn dispatch_replays(&self) -> bool
+    {
+        let now = Instant::now();
+        let windows: Vec<_> = self.win_data.borrow().values().filter_map(|wd| wd.upgrade()).collect();
+        let mut got_event = false;
+        for wd in windows
+        {
+            let mut replay = wd.replay.borrow_mut();
+            while replay.front().is_some_and(|r| r.0 <= now)
+            {
+                let (_, ev, time) = replay.pop_front().unwrap();
+                wd.ev_queue.borrow_mut().push_back((ev, time));
+                got_event = true;
+            }
+        }
+        got_event
+    }
+
+    // blocks until the X connection (or the proxy pipe) has data to read, or the deadline/next timer expires
+    fn wait_readable(&self, deadline: Option<Instant>)
+    {
+        let deadline = match (deadline, self.next_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // round up, so we don't spin when there is less than 1ms left
+        let ms = deadline.map_or(-1, |d| {
+            let t = d.saturating_duration_since(Instant::now());
+            let ms = t.as_secs().saturating_mul(1000) + t.subsec_nanos().div_ceil(1_000_000) as u64;
+            ms.min(i32::MAX as u64) as i32
+        });
+        let mut pfds = [
+            libc::pollfd{ fd: self.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd{ fd: self.proxy.read_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        // errors (like EINTR) are handled by the caller checking the deadline again
+        unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, ms) };
+    }
+
+    // returns the pending asynchronous error, if any
+    fn check_errors(&self) -> Result<(), Error>
+    {
+        if xerror::is_disconnected(self.handle)
+        {
+            return Err(Error::Disconnected)
+        }
+        match xerror::pop_error(self.handle) {
+            Some(err) => Err(Error::Protocol(err)),
+            None => Ok(()),
+        }
+    }
+
+    // runs the requests in f and waits for the server to process them, so errors are tied to this call
+    fn checked<R, F>(&self, request: &'static str, f: F) -> Result<R, Error>
+        where F: FnOnce() -> R
+    {
+        let serial = unsafe { xlib::XNextRequest(self.handle) };
+        let res = f();
+        unsafe { xlib::XSync(self.handle, xlib::False) };
+
+        if xerror::is_disconnected(self.handle)
+        {
+            return Err(Error::Disconnected)
+        }
+        match xerror::take_error_since(self.handle, serial) {
+            Some(mut err) => {
+                err.request = request.into();
+                Err(Error::Protocol(err))
+            },
+            None => Ok(res),
+        }
+    }
+
+    // reads one event from the X queue (blocking) and stores the result, returns true if a window got events
+    fn next_event(&self) -> bool
+    {
+        let mut xevent = unsafe { mem::zeroed() };
+        unsafe{ xlib::XNextEvent(self.handle, &mut xevent); }
+
+        // the input method takes the keys it uses for composing, the rest are sent back to us
+        let filtered = unsafe { xlib::XFilterEvent(&mut xevent, 0) } == xlib::True;
+        // the preedit callbacks run inside XFilterEvent
+        let got_preedit = self.dispatch_preedit();
+        if filtered
+        {
+            return got_preedit
+        }
+
+        let (win, parse_res) = self.parse_event(xevent);
+        // events without a timestamp of their own get the last one seen
+        let time = self.last_time.get();
+
+        match parse_res {
+            // nothing to deliver, don't wake up the caller
+            ParsedEvent::Display(ref events) if events.is_empty() => return got_preedit,
+            ParsedEvent::Many(ref events) if events.is_empty() => return got_preedit,
+            ParsedEvent::Display(events) => {
+                self.ev_queue.borrow_mut().extend(events.into_iter().map(|ev| (ev, time)));
+                return true
+            },
+            _ => (),
+        }
+
+        let got_event = self.with_windata(win, move |wd| {
+            // while replaying, the recording takes the place of live input
+            if !wd.replay.borrow().is_empty() { return false }
+            match parse_res {
+                ParsedEvent::One(event) => {
+                    wd.ev_queue.borrow_mut().push_back((event, time));
+                    true
+                },
+                ParsedEvent::Many(events) => {
+                    wd.ev_queue.borrow_mut().extend(events.into_iter().map(|ev| (ev, time)));
+                    true
+                },
+                ParsedEvent::None | ParsedEvent::Display(_) => false,
+            }
+        });
+        got_event || got_preedit
+    }
+
+    // moves the preedit events generated by the XIM callbacks into their window queues
+    fn dispatch_preedit(&self) -> bool
+    {
+        let time = self.last_time.get();
+        let windows: Vec<_> = self.win_data.borrow().values().filter_map(|wd| wd.upgrade()).collect();
+        let mut got_event = false;
+        for wd in windows
+        {
+            let events = wd.ime.take_events();
+            if events.is_empty() || !wd.replay.borrow().is_empty() { continue }
+            wd.ev_queue.borrow_mut().extend(events.into_iter().map(|ev| {
+                let ev = match ev {
+                    ime::PreeditEvent::Start => Event::PreeditStart,
+                    ime::PreeditEvent::Update(text, cursor) => Event::PreeditUpdate(text, cursor),
+                    ime::PreeditEvent::End => Event::PreeditEnd,
+                };
+                (ev, time)
+            }));
+            got_event = true;
+        }
+        got_event
+    }
+
+    // pull events that aren't tied to a window
+    pub fn consume_event(&self) -> Option<(DisplayEvent<U>, Timestamp)>
+    {
+        self.ev_queue.borrow_mut().pop_front()
+    }
+
+    // converts server time to our monotonic clock, None until we received some timed event
+    pub fn timestamp_to_instant(&self, time: Timestamp) -> Option<Instant>
+    {
+        self.time_ref.get().and_then(|(ref_time, ref_instant)| {
+            // signed difference, so times before the reference work too
+            let diff = time.0.wrapping_sub(ref_time.0) as i32;
+            if diff >= 0
+            {
+                ref_instant.checked_add(Duration::from_millis(diff as u64))
+            }
+            else
+            {
+                ref_instant.checked_sub(Duration::from_millis(-(diff as i64) as u64))
+            }
+        })
+    }
 
-            if got_event { break }
+    fn update_time(&self, time: xlib::Time)
+    {
+        // CurrentTime, WMs often send it in WM_DELETE_WINDOW. it would seed a bogus reference
+        if time == xlib::CurrentTime
+        {
+            return
+        }
+        let time = Timestamp(time as u32);
+        self.last_time.set(time);
+
+        // keep the reference from the event that arrived with the least delay
+        let now = Instant::now();
+        let better = match self.timestamp_to_instant(time) {
+            Some(expected) => now < expected,
+            None => true,
+        };
+        if better
+        {
+            self.time_ref.set(Some((time, now)));
         }
     }
 
-    fn parse_event(&self, mut xevent: xlib::XEvent) -> (xlib::Window, ParsedEvent)
+    fn parse_event(&self, mut xevent: xlib::XEvent) -> (xlib::Window, ParsedEvent<U>)
     {
         match xevent.get_type() {
             xlib::KeyPress => {
-                let ev: &xlib::XKeyPressedEvent = xevent.as_ref();
-                let key = self.scancode_to_key(ev.keycode as u8);
-                (ev.window, ParsedEvent::One(Event::Keyboard(EvState::Pressed, key)))
+                let ev: &mut xlib::XKeyPressedEvent = xevent.as_mut();
+                self.update_time(ev.time);
+                // grabbed hotkeys, they get the time set above like any other display event
+                if ev.window == self.root_win
+                {
+                    return (0, ParsedEvent::Display(self.hotkey_events(ev, EvState::Pressed)))
+                }
+                let repeat = self.input.borrow_mut().key_pressed(ev.keycode as xlib::KeyCode);
+                let key_ev = translate::key_event(self, ev, EvState::Pressed, repeat);
+                let text = self.with_windata(ev.window, |wd| ime::lookup_text(wd.ic.get(), ev));
+                match text.and_then(|t| translate::text_event(&t)) {
+                    Some(text_ev) => (ev.window, ParsedEvent::Many(vec![key_ev, text_ev])),
+                    None => (ev.window, ParsedEvent::One(key_ev)),
+                }
             },
             xlib::KeyRelease => {
                 let ev: &xlib::XKeyReleasedEvent = xevent.as_ref();
-                let key = self.scancode_to_key(ev.keycode as u8);
-                (ev.window, ParsedEvent::One(Event::Keyboard(EvState::Released, key)))
+                self.update_time(ev.time);
+                if ev.window == self.root_win
+                {
+                    return (0, ParsedEvent::Display(self.hotkey_events(ev, EvState::Released)))
+                }
+                self.input.borrow_mut().key_released(ev.keycode as xlib::KeyCode);
+                (ev.window, ParsedEvent::One(translate::key_event(self, ev, EvState::Released, false)))
             },
             xlib::EnterNotify => {
                 let ev: &xlib::XEnterWindowEvent = xevent.as_ref();
+                self.update_time(ev.time);
                 if ev.mode == xlib::NotifyNormal ||
                   (ev.mode == xlib::NotifyUngrab && ev.detail == xlib::NotifyNonlinear)
                 {
@@ -390,6 +898,7 @@ impl XDisplay
             },
             xlib::LeaveNotify => {
                 let ev: &xlib::XLeaveWindowEvent = xevent.as_ref();
+                self.update_time(ev.time);
                 match ev.mode {
                     xlib::NotifyNormal => (ev.window, ParsedEvent::One(Event::PointerInside(false))),
                     _ => (ev.window, ParsedEvent::None)
@@ -398,42 +907,47 @@ impl XDisplay
             xlib::FocusIn => {
                 let ev: &xlib::XFocusInEvent = xevent.as_ref();
                 match ev.mode {
-                    xlib::NotifyNormal | xlib::NotifyWhileGrabbed => (ev.window, ParsedEvent::One(Event::Focused(true))),
+                    xlib::NotifyNormal | xlib::NotifyWhileGrabbed => {
+                        self.with_windata(ev.window, |wd| if !wd.ic.get().is_null() { unsafe { xlib::XSetICFocus(wd.ic.get()) } });
+                        self.input.borrow_mut().set_keys_down(&translate::keymap_keycodes(&self.query_keymap()));
+                        (ev.window, ParsedEvent::One(Event::Focused(true)))
+                    },
                     _ => (ev.window, ParsedEvent::None)
                 }
             },
             xlib::FocusOut => {
                 let ev: &xlib::XFocusOutEvent = xevent.as_ref();
                 match ev.mode {
-                    xlib::NotifyNormal | xlib::NotifyWhileGrabbed => (ev.window, ParsedEvent::One(Event::Focused(false))),
+                    xlib::NotifyNormal | xlib::NotifyWhileGrabbed => {
+                        self.with_windata(ev.window, |wd| if !wd.ic.get().is_null() { unsafe { xlib::XUnsetICFocus(wd.ic.get()) } });
+                        self.input.borrow_mut().clear_keys();
+                        // switching to another window shouldn't leave the pointer trapped in this one
+                        self.drop_pointer_lock(ev.window);
+                        (ev.window, ParsedEvent::One(Event::Focused(false)))
+                    },
                     _ => (ev.window, ParsedEvent::None)
                 }
             },
+            xlib::UnmapNotify => {
+                let ev: &xlib::XUnmapEvent = xevent.as_ref();
+                self.drop_pointer_lock(ev.window);
+                (ev.window, ParsedEvent::None)
+            },
             xlib::Expose => {
                 let ev: &xlib::XExposeEvent = xevent.as_ref();
                 (ev.window, ParsedEvent::One(Event::Redraw))
             },
             xlib::ConfigureNotify => {
                 let ev: &xlib::XConfigureEvent = xevent.as_ref();
+                if ev.window == self.root_win
+                {
+                    return (ev.window, ParsedEvent::Display(vec![DisplayEvent::ScreenChanged(ev.width as u32, ev.height as u32)]))
+                }
                 (ev.window, self.with_windata(ev.window, |wd| {
-                    let mut events = Vec::with_capacity(2);
-                    let size = (ev.width as u32, ev.height as u32);
-                    if wd.size.get() != size
-                    {
-                        wd.size.set(size);
-                        events.push(Event::Resized(size.0, size.1));
-                    }
-
-                    if ev.above == 0  // when .above is set, event contains a bogus position value
-                    {
-                        let pos = (ev.x, ev.y);
-                        if wd.pos.get() != pos
-                        {
-                            wd.pos.set(pos);
-                            events.push(Event::Moved(pos.0, pos.1));
-                        }
-                    }
-
+                    let (mut size, mut pos) = (wd.size.get(), wd.pos.get());
+                    let events = translate::configure_events(ev, &mut size, &mut pos);
+                    wd.size.set(size);
+                    wd.pos.set(pos);
                     if !events.is_empty()
                     {
                         ParsedEvent::Many(events)
@@ -446,10 +960,24 @@ impl XDisplay
                 if ev.message_type == self.atoms.wm_protocols && ev.format == 32 &&
                     (ev.data.get_long(0) as xlib::Atom) == self.atoms.wm_delete_window
                 {
+                    self.update_time(ev.data.get_long(1) as xlib::Time);
                     (ev.window, ParsedEvent::One(Event::CloseButton))
                 }
                 else { (ev.window, ParsedEvent::None) }
             },
+            xlib::MappingNotify => {
+                let ev: &mut xlib::XMappingEvent = xevent.as_mut();
+                if ev.request == xlib::MappingPointer
+                {
+                    return (0, ParsedEvent::None)
+                }
+                // XkbMapNotify normally replaces this, but keep the core keysym tables in sync anyway
+                unsafe { xlib::XRefreshKeyboardMapping(ev); }
+                self.load_modifier_map();
+                self.load_key_groups();
+                self.regrab_hotkeys();
+                (0, ParsedEvent::Display(vec![DisplayEvent::KeymapChanged]))
+            },
             xlib::GenericEvent => {
                 let ev: &mut xlib::XGenericEventCookie = xevent.as_mut();
                 if unsafe { xlib::XGetEventData(self.handle, ev) } == xlib::False
@@ -463,6 +991,17 @@ impl XDisplay
                 unsafe { xlib::XFreeEventData(self.handle, ev); }
                 event
             },
+            t if t == self.xkb_event => self.parse_xkb_event(&mut xevent),
+            t if self.rr_event != 0 && (t == self.rr_event + xrandr::RRScreenChangeNotify || t == self.rr_event + xrandr::RRNotify) => {
+                // keeps the screen size Xlib reports up to date
+                unsafe { xrandr::XRRUpdateConfiguration(&mut xevent) };
+                // a single change sends a burst of these, report it once per dispatch
+                if self.monitors_changed.replace(true)
+                {
+                    return (0, ParsedEvent::None)
+                }
+                (0, ParsedEvent::Display(vec![DisplayEvent::MonitorsChanged]))
+            },
             _ => {
                 let ev: &xlib::XAnyEvent = xevent.as_ref();
                 (ev.window, ParsedEvent::None)
@@ -470,9 +1009,59 @@ impl XDisplay
         }
     }
 
-    fn parse_xinput_event(&self, ev: &xlib::XGenericEventCookie) -> (xlib::Window, ParsedEvent)
+    fn parse_xkb_event(&self, xevent: &mut xlib::XEvent) -> (xlib::Window, ParsedEvent<U>)
     {
-        let ev_data: &xinput2::XIDeviceEvent = unsafe { &*(ev.data as *const _) };
+        let ev: &xlib::XkbAnyEvent = unsafe { &*(xevent as *const _ as *const _) };
+        self.update_time(ev.time);
+        match ev.xkb_type {
+            xlib::XkbNewKeyboardNotify => {
+                self.load_modifier_map();
+                self.load_key_groups();
+                self.regrab_hotkeys();
+                self.load_layouts();
+                (0, ParsedEvent::Display(vec![DisplayEvent::KeymapChanged]))
+            },
+            xlib::XkbMapNotify => {
+                // updates the keysym tables used by XkbKeycodeToKeysym
+                unsafe { xlib::XkbRefreshKeyboardMapping(xevent as *mut _ as *mut _) };
+                self.load_modifier_map();
+                self.load_key_groups();
+                self.regrab_hotkeys();
+                (0, ParsedEvent::Display(vec![DisplayEvent::KeymapChanged]))
+            },
+            xlib::XkbStateNotify => {
+                let ev: &xlib::XkbStateNotifyEvent = unsafe { &*(xevent as *const _ as *const _) };
+                let group = ev.group as u8;
+                if group == self.group.get()
+                {
+                    return (0, ParsedEvent::None)
+                }
+                self.group.set(group);
+                (0, ParsedEvent::Display(vec![self.layout_event()]))
+            },
+            xlib::XkbNamesNotify => {
+                self.load_layouts();
+                (0, ParsedEvent::Display(vec![self.layout_event()]))
+            },
+            _ => (0, ParsedEvent::None)
+        }
+    }
+
+    fn parse_xinput_event(&self, ev: &xlib::XGenericEventCookie) -> (xlib::Window, ParsedEvent<U>)
+    {
+        // all XI2 events start with the same header
+        let ev_header: &xinput2::XIEvent = unsafe { &*(ev.data as *const _) };
+        self.update_time(ev_header.time);
+
+        // raw events don't have a window, they go to the one that has the pointer locked
+        if ev.evtype == xinput2::XI_RawMotion
+        {
+            let ev_data: &xinput2::XIRawEvent = unsafe { &*(ev.data as *const _) };
+            let event = unsafe { self.input.borrow().raw_motion(ev_data) };
+            return (self.pointer_lock.get(), event.map_or(ParsedEvent::None, ParsedEvent::One))
+        }
+
+        let ev_data: &xinput2::XIDeviceEvent = unsafe { &*(ev.data as *const _) };
 
         (ev_data.event, match ev.evtype {
             /*xinput2::XI_DeviceChanged => {
@@ -480,140 +1069,72 @@ impl XDisplay
                 println!("-- device changed!");
                 ParsedEvent::None
             },*/
+            // touches come as Event::Touch, drop the pointer events emulated from them
+            xinput2::XI_ButtonPress | xinput2::XI_ButtonRelease | xinput2::XI_Motion
+                if ev_data.flags & xinput2::XIPointerEmulated != 0 && self.gets_touches() => ParsedEvent::None,
+            xinput2::XI_KeyPress => {
+                self.input.borrow_mut().key_pressed(ev_data.detail as xlib::KeyCode);
+                ParsedEvent::One(translate::xi_key_event(self, ev_data, EvState::Pressed))
+            },
+            xinput2::XI_KeyRelease => {
+                self.input.borrow_mut().key_released(ev_data.detail as xlib::KeyCode);
+                ParsedEvent::One(translate::xi_key_event(self, ev_data, EvState::Released))
+            },
             xinput2::XI_ButtonPress | xinput2::XI_ButtonRelease => {
-                let button_id = ev_data.detail;
                 let state = if ev.evtype == xinput2::XI_ButtonPress { EvState::Pressed } else { EvState::Released };
-                if button_id >= 4 && button_id <= 7  // is wheel
-                {
-                    if ev_data.flags & xinput2::XIPointerEmulated != 0  // emulated event, real data is in XI_Motion
-                    {
-                        ParsedEvent::None
-                    }
-                    else
-                    {
-                        ParsedEvent::One(Event::AxisMoved(as_axis_scroll(button_id)))
-                    }
-                }
-                else
-                {
-                    ParsedEvent::One(Event::MouseButton(state, as_button(button_id), (ev_data.event_x, ev_data.event_y)))
-                }
+                translate::button_event(ev_data, state, &self.mod_map.get()).map_or(ParsedEvent::None, ParsedEvent::One)
             },
             xinput2::XI_Motion => {
-                let axis_state = ev_data.valuators;
-                let axis_mask = unsafe{ slice::from_raw_parts(axis_state.mask, axis_state.mask_len as usize) };
-
-                let (mut scroll_x, mut scroll_y) = (0.0, 0.0);
-                let mut pressure = None;
-                let (mut tilt_x_changed, mut tilt_y_changed) = (false, false);
-                let (mut tilt_x, mut tilt_y) = (0.0, 0.0);
-
-                let mut devices = self.devices.borrow_mut();
-                let dev_info = devices.get_mut(&ev_data.sourceid).unwrap();
-
-                let mut cur_offset = 0;
-                for axis_id in 0 .. dev_info.num_axis
+                let mut events = unsafe { self.input.borrow_mut().motion_events(ev_data) };
+                if ev_data.event == self.pointer_lock.get()
                 {
-                    if xinput2::XIMaskIsSet(axis_mask, axis_id)
-                    {
-                        let axis_value = unsafe { *axis_state.values.offset(cur_offset) };
-                        if let Some(axis_info) = dev_info.axis_info.get_mut(&axis_id)
-                        {
-                            match axis_info.axis_type {
-                                AxisType::ScrollVertical(incr) => if axis_info.value != axis_value
-                                {
-                                    scroll_y = (axis_info.value - axis_value) / incr;
-                                },
-                                AxisType::ScrollHorizontal(incr) => if axis_info.value != axis_value
-                                {
-                                    scroll_x = (axis_info.value - axis_value) / incr;
-                                },
-                                AxisType::Pressure(max) => if axis_info.value != axis_value
-                                {
-                                    pressure = Some(axis_value / max);
-                                },
-                                // assuming those two are always present in pairs
-                                AxisType::TiltX(max) => {
-                                    let val = if axis_info.value != axis_value
-                                    {
-                                        tilt_x_changed = true;
-                                        axis_value
-                                    }
-                                    else
-                                    {
-                                        axis_info.value
-                                    };
-                                    tilt_x = (val / max).min(1.0).max(-1.0);
-                                },
-                                AxisType::TiltY(max) => {
-                                    let val = if axis_info.value != axis_value
-                                    {
-                                        tilt_y_changed = true;
-                                        axis_value
-                                    }
-                                    else
-                                    {
-                                        axis_info.value
-                                    };
-                                    tilt_y = (val / max).min(1.0).max(-1.0);
-                                },
-                            }
-
-                            axis_info.value = axis_value;
-                        }
-
-                        cur_offset += 1;
-                    }
+                    // the position is meaningless while locked, movement comes as MouseDelta
+                    events.retain(|ev| !matches!(*ev, Event::MouseMoved(..)));
+                    self.center_pointer(ev_data.event, Some((ev_data.event_x, ev_data.event_y)));
                 }
-
-                let mut events = Vec::with_capacity(4);
-
-                let pointer_pos = (ev_data.root_x, ev_data.root_y);
-                if self.pointer_pos.get() != pointer_pos
-                {
-                    self.pointer_pos.set(pointer_pos);
-                    events.push(Event::MouseMoved(ev_data.event_x, ev_data.event_y));
-                }
-                if scroll_x != 0.0 || scroll_y != 0.0
-                {
-                    events.push(Event::AxisMoved(AxisState::Scroll(scroll_x, scroll_y)))
-                }
-                if let Some(val) = pressure
-                {
-                    events.push(Event::AxisMoved(AxisState::Pressure(val)))
-                }
-                if tilt_x_changed || tilt_y_changed
-                {
-                    events.push(Event::AxisMoved(AxisState::Tilt(tilt_x, tilt_y)))
-                }
-
                 ParsedEvent::Many(events)
             },
+            xinput2::XI_TouchBegin | xinput2::XI_TouchUpdate | xinput2::XI_TouchEnd => {
+                let state = match ev.evtype {
+                    xinput2::XI_TouchBegin => TouchState::Begin,
+                    xinput2::XI_TouchUpdate => TouchState::Update,
+                    _ => TouchState::End,
+                };
+                ParsedEvent::One(unsafe { self.input.borrow().touch_event(ev_data, state) })
+            },
             xinput2::XI_HierarchyChanged => {
                 let ev_data: &xinput2::XIHierarchyEvent = unsafe { &*(ev.data as *const _) };
                 let ev_info = unsafe { slice::from_raw_parts(ev_data.info, ev_data.num_info as usize) };
 
+                let mut events = Vec::new();
                 if ev_data.flags & (xinput2::XIDeviceEnabled | xinput2::XIDeviceDisabled) != 0
                 {
                     for info in ev_info
                     {
-                        if info._use == xinput2::XISlavePointer
+                        if info._use == xinput2::XISlavePointer || info._use == xinput2::XISlaveKeyboard
                         {
                             if info.flags & xinput2::XIDeviceEnabled != 0
                             {
-                                println!("** adding device: {}", info.deviceid);
-                                self.load_axis_info(info.deviceid);
+                                if info._use == xinput2::XISlavePointer
+                                {
+                                    self.load_axis_info(info.deviceid);
+                                }
+                                events.push(DisplayEvent::DeviceAdded(info.deviceid));
                             }
                             else if info.flags & xinput2::XIDeviceDisabled != 0
                             {
-                                self.devices.borrow_mut().remove(&info.deviceid);
-                                println!("** removed device: {}", info.deviceid);
+                                self.input.borrow_mut().remove_device(info.deviceid);
+                                events.push(DisplayEvent::DeviceRemoved(info.deviceid));
                             }
                         }
                     }
                 }
 
-                return (0, ParsedEvent::None)
+                if events.is_empty()
+                {
+                    return (0, ParsedEvent::None)
+                }
+                return (0, ParsedEvent::Display(events))
             },
             _ => return (0, ParsedEvent::None)
         })
@@ -624,92 +1145,293 @@ impl XDisplay
         let mut ndevices = 0;
         let devices_ptr = unsafe { xinput2::XIQueryDevice(self.handle, device_id, &mut ndevices) };
         let xi_devices = unsafe { slice::from_raw_parts(devices_ptr, ndevices as usize) };
+        let labels = translate::AxisLabels{
+            pressure: self.atoms.abs_pressure,
+            tilt_x: self.atoms.abs_tilt_x,
+            mt_pressure: self.atoms.abs_mt_pressure,
+            tilt_y: self.atoms.abs_tilt_y,
+        };
+        let mut input = self.input.borrow_mut();
 
         for dev in xi_devices
         {
-            // slave pointers are the physical devices that can have multiple axis
-            if dev._use == xinput2::XISlavePointer
+            unsafe { input.add_device(dev, &labels) };
+        }
+
+        unsafe{ xinput2::XIFreeDeviceInfo(devices_ptr); }
+    }
+
+    // invisible cursor, created on first use
+    fn blank_cursor(&self) -> xlib::Cursor
+    {
+        if self.blank_cursor.get() == 0
+        {
+            self.blank_cursor.set(unsafe { cursor::create_blank(self.handle, self.root_win) });
+        }
+        self.blank_cursor.get()
+    }
+
+    // a standard cursor from the user's theme, loaded on first use and shared by every window
+    pub fn shape_cursor(&self, shape: CursorShape) -> Result<CursorId, Error>
+    {
+        if let Some(&cursor) = self.shape_cursors.borrow().get(&shape)
+        {
+            return Ok(CursorId(cursor))
+        }
+        let cursor = unsafe { cursor::load_shape(self.handle, shape) };
+        if cursor == 0
+        {
+            return Err(Error::XRequest("XCreateFontCursor"))
+        }
+        self.shape_cursors.borrow_mut().insert(shape, cursor);
+        Ok(CursorId(cursor))
+    }
+
+    // rgba_data has width * height pixels, not premultiplied. the hotspot is the pixel that does the pointing
+    pub fn create_cursor(&self, width: u32, height: u32, rgba_data: &[u8], hotspot: (u32, u32)) -> Result<CursorId, Error>
+    {
+        let size = (width as usize).checked_mul(height as usize).and_then(|px| px.checked_mul(4));
+        if size != Some(rgba_data.len())
+        {
+            return Err(Error::ImageSize(width, height, rgba_data.len()))
+        }
+        let cursor = self.checked("XcursorImageLoadCursor", || unsafe {
+            cursor::create_rgba(self.handle, width, height, rgba_data, hotspot)
+        })?;
+        if cursor == 0
+        {
+            return Err(Error::XRequest("XcursorImageLoadCursor"))
+        }
+        Ok(CursorId(cursor))
+    }
+
+    // frees a cursor from create_cursor. windows showing it keep it until they change cursor
+    pub fn free_cursor(&self, id: CursorId)
+    {
+        // the shape cursors are shared, they live as long as the display
+        if self.shape_cursors.borrow().values().any(|&c| c == id.0)
+        {
+            return
+        }
+        for wd in self.win_data.borrow().values().filter_map(|wd| wd.upgrade())
+        {
+            if wd.cursor.get() == id.0
             {
-                println!("-- device {}", dev.deviceid);
+                wd.cursor.set(0);
+            }
+        }
+        unsafe { xlib::XFreeCursor(self.handle, id.0) };
+    }
+
+    // raw events only go to the root window, we only want them while the pointer is locked
+    fn select_raw_motion(&self, enable: bool)
+    {
+        let mut mask = [0; 3];
+        if enable
+        {
+            xinput2::XISetMask(&mut mask, xinput2::XI_RawMotion);
+        }
+        let mut event_mask = xinput2::XIEventMask{
+            deviceid: xinput2::XIAllMasterDevices,
+            mask_len: mask.len() as i32,
+            mask: mask.as_mut_ptr(),
+        };
+        unsafe { xinput2::XISelectEvents(self.handle, self.root_win, &mut event_mask, 1) };
+    }
+
+    // keeps the locked pointer in the middle of the window, so it never stops at an edge
+    fn center_pointer(&self, win: xlib::Window, pos: Option<(f64, f64)>)
+    {
+        let (width, height) = self.with_windata(win, |wd| wd.size.get());
+        let center = ((width / 2) as i32, (height / 2) as i32);
+        // the warp sends a motion event too, don't loop on it
+        if pos.is_some_and(|(x, y)| (x as i32, y as i32) == center)
+        {
+            return
+        }
+        unsafe { xlib::XWarpPointer(self.handle, 0, win, 0, 0, 0, 0, center.0, center.1) };
+    }
+
+    // while the pointer is grabbed the server sends touches only as emulated pointer events
+    fn gets_touches(&self) -> bool
+    {
+        self.has_touch && self.pointer_lock.get() == 0 && self.pointer_grab.get() == 0
+    }
+
+    // the server also releases the grab by itself when the window is unmapped. returns false if win wasn't locked
+    fn drop_pointer_lock(&self, win: xlib::Window) -> bool
+    {
+        if self.pointer_lock.get() != win
+        {
+            return false
+        }
+        self.pointer_lock.set(0);
+        self.select_raw_motion(false);
+        unsafe { xlib::XUngrabPointer(self.handle, xlib::CurrentTime) };
+        true
+    }
+
+    // the master pointer and keyboard used by this client
+    fn master_devices(&self) -> Result<(i32, i32), Error>
+    {
+        let mut pointer = 0;
+        if unsafe { xinput2::XIGetClientPointer(self.handle, 0, &mut pointer) } == xlib::False
+        {
+            return Err(Error::XRequest("XIGetClientPointer"))
+        }
 
-                let mut values = vec![0.0; dev.num_classes as usize];
-                let mut has_scroll = false;
+        let mut ndevices = 0;
+        let info = unsafe { xinput2::XIQueryDevice(self.handle, pointer, &mut ndevices) };
+        if info.is_null() || ndevices < 1
+        {
+            return Err(Error::XRequest("XIQueryDevice"))
+        }
+        let keyboard = unsafe { (*info).attachment };
+        unsafe { xinput2::XIFreeDeviceInfo(info) };
+        Ok((pointer, keyboard))
+    }
 
-                for i in 0 .. dev.num_classes as isize
+    // finds out which ModN bits are used for Alt, Super and NumLock
+    fn load_modifier_map(&self)
+    {
+        let mut mod_keysyms = Vec::new();
+        unsafe
+        {
+            let modmap = xlib::XGetModifierMapping(self.handle);
+            if modmap.is_null() { return }
+            let per_mod = (*modmap).max_keypermod as usize;
+            let keycodes = slice::from_raw_parts((*modmap).modifiermap, 8 * per_mod);
+            for (i, &keycode) in keycodes.iter().enumerate()
+            {
+                if keycode != 0
                 {
-                    let class = unsafe { *dev.classes.offset(i) };
-                    let ctype = unsafe { (*class)._type };
-
-                    let (ax_num, ax_data) = match ctype {
-                        xinput2::XIValuatorClass => {
-                            let ci: &xinput2::XIValuatorClassInfo = unsafe { &*(class as *const _) };
-                            println!("valuator {}: mode={} min={} max={} val={}", ci.number, ci.mode, ci.min, ci.max, ci.value);
-                            // we're gonna assume valuators appear before scroll classes, so we can store them here ...
-                            values[ci.number as usize] = ci.value;
-
-                            let ax_type = match ci.label {
-                                a if a == self.atoms.abs_pressure => AxisType::Pressure(ci.max),
-                                // the tilt value should be "almost" symmetric (like -64 to 63)
-                                a if a == self.atoms.abs_tilt_x => AxisType::TiltX(ci.max),
-                                a if a == self.atoms.abs_tilt_y => AxisType::TiltY(ci.max),
-                                _ => continue
-                            };
-                            (ci.number, AxisData{ axis_type: ax_type, value: ci.value })
-                        },
-                        xinput2::XIScrollClass => {
-                            let ci: &xinput2::XIScrollClassInfo = unsafe { &*(class as *const _) };
-                            println!("scroll {}: type={} incr={} flags={}", ci.number, ci.scroll_type, ci.increment, ci.flags);
-                            has_scroll = true;
-                            // ... and use them here to fill in the scroll classes
-                            match ci.scroll_type {
-                                xinput2::XIScrollTypeVertical => {
-                                    (ci.number, AxisData{ axis_type: AxisType::ScrollVertical(ci.increment), value: values[ci.number as usize] })
-                                },
-                                xinput2::XIScrollTypeHorizontal => {
-                                    (ci.number, AxisData{ axis_type: AxisType::ScrollHorizontal(ci.increment), value: values[ci.number as usize] })
-                                },
-                                _ => continue
-                            }
-                        },
-                        _ => continue
-                    };
-
-                    let mut devices = self.devices.borrow_mut();
-                    let dev_info = devices.entry(dev.deviceid).or_insert_with(Default::default);
-                    dev_info.axis_info.insert(ax_num, ax_data);
-                    dev_info.has_scroll = has_scroll;
-                    // store the highest axis id we need to read from events
-                    if ax_num + 1 > dev_info.num_axis
-                    {
-                        dev_info.num_axis = ax_num + 1;
-                    }
+                    mod_keysyms.push((i / per_mod, xlib::XkbKeycodeToKeysym(self.handle, keycode, 0, 0)));
                 }
             }
+            xlib::XFreeModifiermap(modmap);
         }
+        self.mod_map.set(translate::ModifierMap::from_keysyms(&mod_keysyms));
+    }
 
-        unsafe{ xinput2::XIFreeDeviceInfo(devices_ptr); }
+    // how many groups each key has, XkbKeycodeToKeysym doesn't map the active group into them
+    fn load_key_groups(&self)
+    {
+        let mut groups = Vec::new();
+        unsafe
+        {
+            let desc = xlib::XkbGetMap(self.handle, xkb::KEY_SYMS_MASK, xkb::USE_CORE_KBD);
+            if desc.is_null() { return }
+            let map = (*desc).map as *const xkb::ClientMapRec;
+            if !map.is_null() && !(*map).key_sym_map.is_null()
+            {
+                // allocated for every keycode up to max_key_code
+                let sym_maps = slice::from_raw_parts((*map).key_sym_map, (*desc).max_key_code as usize + 1);
+                groups = sym_maps.iter().map(|sm| sm.group_info).collect();
+            }
+            xlib::XkbFreeKeyboard(desc, 0, xlib::True);
+        }
+        *self.key_groups.borrow_mut() = groups;
     }
 
-    fn reload_scroll_values(&self)
+    // reads the active group and the names of the configured layouts
+    fn load_layouts(&self)
     {
-        let scroll_devs: Vec<_> = self.devices.borrow().iter()
-            .filter(|&(_, info)| info.has_scroll)
-            .map(|(&dev, _)| dev).collect();
+        let mut state = xkb::StateRec::default();
+        if unsafe { xkb::XkbGetState(self.handle, xkb::USE_CORE_KBD, &mut state) } == xlib::Success as c_int
+        {
+            self.group.set(state.group);
+        }
 
-        for dev_id in scroll_devs
+        let mut names = Vec::new();
+        unsafe
         {
-            self.load_axis_info(dev_id);
+            let desc = xlib::XkbAllocKeyboard();
+            if desc.is_null() { return }
+            if xlib::XkbGetNames(self.handle, xkb::GROUP_NAMES_MASK as c_uint, desc) == xlib::Success as c_int
+            {
+                for &atom in (*(*desc).names).groups.iter().take_while(|&&atom| atom != 0)
+                {
+                    let name = xlib::XGetAtomName(self.handle, atom);
+                    if name.is_null() { break }
+                    names.push(CStr::from_ptr(name).to_string_lossy().into_owned());
+                    xlib::XFree(name as *mut _);
+                }
+            }
+            xlib::XkbFreeKeyboard(desc, 0, xlib::True);
+        }
+        *self.group_names.borrow_mut() = names;
+    }
+
+    fn layout_event(&self) -> DisplayEvent<U>
+    {
+        let (group, name) = self.current_layout();
+        DisplayEvent::LayoutChanged(group, name)
+    }
+
+    // active keyboard group and its name (like "English (US)"), the name is empty if the server doesn't have one
+    pub fn current_layout(&self) -> (u8, String)
+    {
+        let group = self.group.get();
+        (group, self.group_names.borrow().get(group as usize).cloned().unwrap_or_default())
+    }
+
+    // what the key types on the current layout ("Q", "Ä"), or its keysym name for keys that don't type anything
+    pub fn key_label(&self, key: Key) -> String
+    {
+        match shortcut::key_to_keysym(key) {
+            Some(keysym) => self.keysym_label(keysym as xlib::KeySym),
+            None => shortcut::key_to_string(key),
+        }
+    }
+
+    // label for the key at that position on the current layout, like "Z" for ScanCode::Y on a german layout
+    pub fn scancode_label(&self, scancode: ScanCode) -> String
+    {
+        let keysym = translate::scancode_to_keycode(scancode)
+            .map(|kc| translate::Keymap::keycode_to_keysym(self, kc, self.group.get(), 0))
+            .unwrap_or(0);
+        if keysym == 0
+        {
+            return scancode.name().map(Into::into).unwrap_or_default()
+        }
+        self.keysym_label(keysym)
+    }
+
+    fn keysym_label(&self, keysym: xlib::KeySym) -> String
+    {
+        let mut keysym = keysym;
+        let mut buf = [0u8; 16];
+        let mut extra = 0;
+        let len = unsafe { xlib::XkbTranslateKeySym(self.handle, &mut keysym, 0, buf.as_mut_ptr() as *mut _,
+            buf.len() as c_int, &mut extra) };
+        let text = String::from_utf8_lossy(&buf[.. len.max(0) as usize]).into_owned();
+
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_control() => {
+                // keycaps show the uppercase letter, but not when it takes more than one char (ß)
+                let mut upper = c.to_uppercase();
+                match (upper.next(), upper.next()) {
+                    (Some(u), None) => u.to_string(),
+                    _ => text,
+                }
+            },
+            _ => shortcut::key_to_string(translate::convert_keysym(keysym as u32)),
         }
     }
 
-    fn scancode_to_key(&self, keycode: xlib::KeyCode) -> Key
+    fn reload_scroll_values(&self)
     {
-        let keysym = unsafe{ xlib::XKeycodeToKeysym(self.handle, keycode, 0) };
-        convert_keysym(keysym as u32)
+        let scroll_devs = self.input.borrow().scroll_devices();
+
+        for dev_id in scroll_devs
+        {
+            self.load_axis_info(dev_id);
+        }
     }
 
-    fn with_windata<T, F>(&self, win: xlib::Window, f: F) -> T
-        where T: Default, F: FnOnce(&WindowData) -> T
+    fn with_windata<R, F>(&self, win: xlib::Window, f: F) -> R
+        where R: Default, F: FnOnce(&WindowData<U>) -> R
     {
         match self.win_data.borrow_mut().entry(win) {
             hash_map::Entry::Occupied(entry) => {
@@ -717,61 +1439,152 @@ impl XDisplay
                     Some(wd) => f(&wd),
                     None => {
                         entry.remove();
-                        T::default()
+                        R::default()
                     },
                 }
             },
-            _ => T::default()
+            _ => R::default()
         }
     }
 }
 
-impl Drop for XDisplay
+impl<U> translate::Keymap for XDisplay<U>
+{
+    fn keycode_to_keysym(&self, keycode: xlib::KeyCode, group: u8, level: u8) -> xlib::KeySym
+    {
+        let lookup = |group: u8| unsafe { xlib::XkbKeycodeToKeysym(self.handle, keycode, group as c_int, level as c_int) };
+        // keys with less groups than the active layout (like the ones shared by every layout) wrap around
+        match self.key_groups.borrow().get(keycode as usize) {
+            Some(&group_info) => translate::effective_group(group_info, group).map_or(0, lookup),
+            // the group map couldn't be loaded, the base group is the best guess
+            None => match lookup(group) {
+                0 if group != 0 => lookup(0),
+                keysym => keysym,
+            },
+        }
+    }
+
+    fn modifier_map(&self) -> translate::ModifierMap
+    {
+        self.mod_map.get()
+    }
+}
+
+// the X connection. events can also sit in Xlib's queue without the fd being readable, see dispatch_pending
+impl<U> AsRawFd for XDisplay<U>
+{
+    fn as_raw_fd(&self) -> RawFd
+    {
+        unsafe { xlib::XConnectionNumber(self.handle) }
+    }
+}
+
+impl<U> Drop for XDisplay<U>
 {
     fn drop(&mut self)
     {
-        unsafe{ xlib::XCloseDisplay(self.handle); }
+        self.proxy.close();
+        unsafe
+        {
+            if self.blank_cursor.get() != 0
+            {
+                xlib::XFreeCursor(self.handle, self.blank_cursor.get());
+            }
+            close_connection(self.handle, self.xim);
+        }
+    }
+}
+
+// owns the connection while XDisplay::with_user_events sets it up, so a failing step doesn't leak it
+struct ConnectionGuard
+{
+    handle: *mut xlib::Display,
+    xim: xlib::XIM,
+}
+
+impl Drop for ConnectionGuard
+{
+    fn drop(&mut self)
+    {
+        unsafe { close_connection(self.handle, self.xim) };
+    }
+}
+
+unsafe fn close_connection(handle: *mut xlib::Display, xim: xlib::XIM)
+{
+    if !xim.is_null()
+    {
+        xlib::XCloseIM(xim);
     }
+    xlib::XCloseDisplay(handle);
+    xerror::unregister(handle);
 }
 
-#[derive(Default)]
-struct WindowData
+struct WindowData<U>
 {
+    ic: Cell<xlib::XIC>,
+    ime: ime::ImeState,
     size: Cell<(u32, u32)>,
+    cursor: Cell<xlib::Cursor>,
+    cursor_hidden: Cell<bool>,
     pos: Cell<(i32, i32)>,
-    ev_queue: RefCell<VecDeque<Event>>,
+    ev_queue: RefCell<VecDeque<(Event<U>, Timestamp)>>,
+    recorder: RefCell<Option<BufWriter<File>>>,
+    record_error: RefCell<Option<io::Error>>,
+    replay: RefCell<VecDeque<(Instant, Event<U>, Timestamp)>>,
 }
 
-pub struct XWindow<'a>
+impl<U> Default for WindowData<U>
 {
-    display: &'a XDisplay,
+    fn default() -> Self
+    {
+        WindowData{
+            ic: Cell::new(ptr::null_mut()),
+            ime: Default::default(),
+            size: Default::default(),
+            cursor: Cell::new(0),
+            cursor_hidden: Cell::new(false),
+            pos: Default::default(),
+            ev_queue: Default::default(),
+            recorder: Default::default(),
+            record_error: Default::default(),
+            replay: Default::default(),
+        }
+    }
+}
+
+pub struct XWindow<'a, U: 'a = ()>
+{
+    display: &'a XDisplay<U>,
     handle: xlib::Window,
     surface: glengine::Surface<'a>,
-    data: Rc<WindowData>,
+    data: Rc<WindowData<U>>,
 }
 
-impl<'a> XWindow<'a>
+impl<'a, U> XWindow<'a, U>
 {
-    fn new(display: &'a XDisplay, width: u32, height: u32) -> Result<Self, &'static str>
+    fn new(display: &'a XDisplay<U>, width: u32, height: u32) -> Result<Self, Error>
     {
-        let win_id = unsafe {
+        let (win_id, event_mask) = unsafe {
             let screen_num = xlib::XDefaultScreen(display.handle);
             let root_win = xlib::XRootWindow(display.handle, screen_num);
             //let black_pixel = xlib::XBlackPixel(display.handle, screen_num);
 
+            let event_mask = xlib::KeyPressMask |
+                             xlib::KeyReleaseMask |
+                             xlib::EnterWindowMask |
+                             xlib::LeaveWindowMask |
+                             xlib::ExposureMask |
+                             xlib::StructureNotifyMask |
+                             xlib::FocusChangeMask;
+
             let mut win_attr = xlib::XSetWindowAttributes{
                 //background_pixel: black_pixel,
-                event_mask: xlib::KeyPressMask |
-                            xlib::KeyReleaseMask |
-                            xlib::EnterWindowMask |
-                            xlib::LeaveWindowMask |
-                            xlib::ExposureMask |
-                            xlib::StructureNotifyMask |
-                            xlib::FocusChangeMask,
+                event_mask,
                 .. mem::zeroed()
             };
 
-            let win_id = xlib::XCreateWindow(display.handle,
+            let win_id = display.checked("XCreateWindow", || xlib::XCreateWindow(display.handle,
                 root_win,       // parent
                 0, 0,           // x, y
                 width, height,
@@ -780,21 +1593,29 @@ impl<'a> XWindow<'a>
                 xlib::InputOutput as u32,   // input class
                 ptr::null_mut(),            // visual
                 xlib::CWBackPixel | xlib::CWEventMask, // value mask
-                &mut win_attr);
+                &mut win_attr))?;
 
             // suscribe to WM close event
             let mut protocols = [display.atoms.wm_delete_window];
             if xlib::XSetWMProtocols(display.handle, win_id, &mut protocols[0], protocols.len() as i32) == xlib::False
             {
                 xlib::XDestroyWindow(display.handle, win_id);
-                return Err("can't set WM protocols");
+                return Err(Error::XRequest("XSetWMProtocols"));
             }
 
             // init XInput events
-            let mut mask = [0];
+            let mut mask = [0; 3];
             xinput2::XISetMask(&mut mask, xinput2::XI_ButtonPress);
             xinput2::XISetMask(&mut mask, xinput2::XI_ButtonRelease);
             xinput2::XISetMask(&mut mask, xinput2::XI_Motion);
+            // without XI_TouchOwnership, the server holds the touch events back until we own the touch,
+            // so we never see touches that a grabbing client (like a gesture handler) takes
+            if display.has_touch
+            {
+                xinput2::XISetMask(&mut mask, xinput2::XI_TouchBegin);
+                xinput2::XISetMask(&mut mask, xinput2::XI_TouchUpdate);
+                xinput2::XISetMask(&mut mask, xinput2::XI_TouchEnd);
+            }
 
             let mut input_event_mask = xinput2::XIEventMask{
                 deviceid: xinput2::XIAllMasterDevices,
@@ -805,15 +1626,17 @@ impl<'a> XWindow<'a>
             if xinput2::XISelectEvents(display.handle, win_id, &mut input_event_mask, 1) != xlib::Success as i32
             {
                 xlib::XDestroyWindow(display.handle, win_id);
-                return Err("Failed to select XInput2 events")
+                return Err(Error::XRequest("XISelectEvents"))
             }
 
-            win_id
+            (win_id, event_mask)
         };
 
         let surface = display.engine.create_window_surface(win_id as _)?;
 
-        let data = Default::default();
+        let data: Rc<WindowData<U>> = Default::default();
+        // the preedit callbacks point into data, the IC is destroyed before it goes away
+        data.ic.set(unsafe { ime::create_ic(display.handle, display.xim, win_id, event_mask, &data.ime) });
         display.win_data.borrow_mut().insert(win_id, Rc::downgrade(&data));
 
         Ok(XWindow{
@@ -835,6 +1658,11 @@ impl<'a> XWindow<'a>
         unsafe{ xlib::XMapWindow(self.display.handle, self.handle); }
     }
 
+    pub fn id(&self) -> WindowId
+    {
+        WindowId(self.handle)
+    }
+
     pub fn get_size(&self) -> (u32, u32)
     {
         self.data.size.get()
@@ -845,9 +1673,247 @@ impl<'a> XWindow<'a>
         self.data.pos.get()
     }
 
-    pub fn consume_event(&self) -> Option<Event>
+    // modifiers active right now, asks the server
+    pub fn get_modifiers(&self) -> Modifiers
+    {
+        let (mut root, mut child) = (0, 0);
+        let (mut root_x, mut root_y, mut win_x, mut win_y) = (0, 0, 0, 0);
+        let mut mask = 0;
+        unsafe { xlib::XQueryPointer(self.display.handle, self.handle, &mut root, &mut child,
+            &mut root_x, &mut root_y, &mut win_x, &mut win_y, &mut mask) };
+        self.display.mod_map.get().convert(mask)
+    }
+
+    pub fn consume_event(&self) -> Option<(Event<U>, Timestamp)>
+    {
+        let ev = self.data.ev_queue.borrow_mut().pop_front();
+        if let Some((ref event, time)) = ev
+        {
+            let mut recorder = self.data.recorder.borrow_mut();
+            let res = match *recorder {
+                Some(ref mut w) => record::write_event(w, event, time).map(|_| ()),
+                None => Ok(()),
+            };
+            // the recording stops here, stop_recording reports the error
+            if let Err(err) = res
+            {
+                *self.data.record_error.borrow_mut() = Some(err);
+                *recorder = None;
+            }
+        }
+        ev
+    }
+
+    // writes every event consumed from this window to a file, until stop_recording is called
+    pub fn start_recording<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        record::write_header(&mut w)?;
+        *self.data.record_error.borrow_mut() = None;
+        *self.data.recorder.borrow_mut() = Some(w);
+        Ok(())
+    }
+
+    // returns the write error that ended the recording early, if any
+    pub fn stop_recording(&self) -> io::Result<()>
+    {
+        if let Some(err) = self.data.record_error.borrow_mut().take()
+        {
+            return Err(err)
+        }
+        match self.data.recorder.borrow_mut().take() {
+            Some(mut w) => w.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // feeds a recording into this window's queue with the original timing.
+    // live input for this window is ignored until the replay ends
+    pub fn start_replay<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        let events = record::read_events(BufReader::new(File::open(path)?))?;
+        let mut deadline = Instant::now();
+        let mut prev_time = events.first().map(|e| e.1).unwrap_or_default();
+
+        // events that go back in time are sent right after the previous one, the queue must stay in order
+        *self.data.replay.borrow_mut() = events.into_iter()
+            .map(|(ev, time)| {
+                if (time.0.wrapping_sub(prev_time.0) as i32) > 0
+                {
+                    deadline += time.duration_since(prev_time);
+                }
+                prev_time = time;
+                (deadline, ev, time)
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool
     {
-        self.data.ev_queue.borrow_mut().pop_front()
+        !self.data.replay.borrow().is_empty()
+    }
+
+    // moves the IME candidate window next to the text cursor, in window coordinates
+    pub fn set_ime_position(&self, x: i32, y: i32)
+    {
+        ime::set_spot(self.data.ic.get(), x, y);
+    }
+
+    // all keyboard input comes to this window until the grab is dropped. the window must be mapped.
+    // also takes the focus. while the focus stays on one of our windows key events arrive there as usual,
+    // if another client moves it away they come here through the grab (without Event::Text)
+    pub fn grab_keyboard(&self) -> Result<DeviceGrab<'a, U>, Error>
+    {
+        let (_, keyboard) = self.display.master_devices()?;
+        let grab = self.grab_device(keyboard, &[xinput2::XI_KeyPress, xinput2::XI_KeyRelease])?;
+        self.display.checked("XSetInputFocus", || unsafe {
+            xlib::XSetInputFocus(self.display.handle, self.handle, xlib::RevertToParent, xlib::CurrentTime)
+        })?;
+        Ok(grab)
+    }
+
+    // pointer events come to this window until the grab is dropped, even from outside of it
+    pub fn grab_pointer(&self) -> Result<DeviceGrab<'a, U>, Error>
+    {
+        let (pointer, _) = self.display.master_devices()?;
+        let grab = self.grab_device(pointer, &[xinput2::XI_ButtonPress, xinput2::XI_ButtonRelease, xinput2::XI_Motion])?;
+        self.display.pointer_grab.set(pointer);
+        Ok(grab)
+    }
+
+    fn grab_device(&self, device_id: i32, events: &[i32]) -> Result<DeviceGrab<'a, U>, Error>
+    {
+        let mut mask = [0];
+        for &ev in events
+        {
+            xinput2::XISetMask(&mut mask, ev);
+        }
+        let mut event_mask = xinput2::XIEventMask{
+            deviceid: device_id,
+            mask_len: mask.len() as i32,
+            mask: mask.as_mut_ptr(),
+        };
+
+        // owner_events, so events on our other windows are still reported to them
+        let status = self.display.checked("XIGrabDevice", || unsafe {
+            xinput2::XIGrabDevice(self.display.handle, device_id, self.handle, xlib::CurrentTime, 0,
+                xinput2::XIGrabModeAsync, xinput2::XIGrabModeAsync, xlib::True, &mut event_mask)
+        })?;
+        grab_result(status, "XIGrabDevice")?;
+        Ok(DeviceGrab{ display: self.display, device_id })
+    }
+
+    // moves the pointer, in window coordinates
+    pub fn set_pointer_position(&self, x: f64, y: f64) -> Result<(), Error>
+    {
+        let (pointer, _) = self.display.master_devices()?;
+        self.display.checked("XIWarpPointer", || unsafe {
+            xinput2::XIWarpPointer(self.display.handle, pointer, 0, self.handle, 0.0, 0.0, 0, 0, x, y)
+        })?;
+        Ok(())
+    }
+
+    // where the pointer is right now, asks the server. fails if it's on another screen
+    pub fn query_pointer(&self) -> Result<PointerState, Error>
+    {
+        let (pointer, _) = self.display.master_devices()?;
+        let (mut root, mut child) = (0, 0);
+        let (mut root_x, mut root_y, mut win_x, mut win_y) = (0.0, 0.0, 0.0, 0.0);
+        let mut buttons: xinput2::XIButtonState = Default::default();
+        let mut mods: xinput2::XIModifierState = Default::default();
+        let mut group: xinput2::XIModifierState = Default::default();
+        let same_screen = self.display.checked("XIQueryPointer", || unsafe {
+            xinput2::XIQueryPointer(self.display.handle, pointer, self.handle, &mut root, &mut child,
+                &mut root_x, &mut root_y, &mut win_x, &mut win_y, &mut buttons, &mut mods, &mut group)
+        })?;
+
+        let pressed = if buttons.mask.is_null()
+        {
+            Vec::new()
+        }
+        else
+        {
+            let mask = unsafe { slice::from_raw_parts(buttons.mask, buttons.mask_len as usize) };
+            let pressed = translate::pressed_buttons(mask);
+            unsafe { xlib::XFree(buttons.mask as *mut _) };
+            pressed
+        };
+        if same_screen == xlib::False
+        {
+            return Err(Error::XRequest("XIQueryPointer"))
+        }
+
+        Ok(PointerState{
+            position: (win_x, win_y),
+            root_position: (root_x, root_y),
+            buttons: pressed,
+            mods: self.display.mod_map.get().convert(mods.effective as c_uint),
+        })
+    }
+
+    // hides the cursor and keeps it inside the window. while locked, movement is reported as unaccelerated
+    // Event::MouseDelta instead of MouseMoved. the window must be mapped
+    pub fn set_pointer_lock(&self, lock: bool) -> Result<(), Error>
+    {
+        let display = self.display;
+        if !lock
+        {
+            if display.drop_pointer_lock(self.handle)
+            {
+                unsafe { xlib::XFlush(display.handle) };
+            }
+            return Ok(())
+        }
+
+        // a core grab, XI2 doesn't have confine_to. with owner_events our XI2 selections keep working
+        let status = display.checked("XGrabPointer", || unsafe {
+            xlib::XGrabPointer(display.handle, self.handle, xlib::True, 0, xlib::GrabModeAsync, xlib::GrabModeAsync,
+                self.handle, display.blank_cursor(), xlib::CurrentTime)
+        })?;
+        grab_result(status, "XGrabPointer")?;
+
+        display.pointer_lock.set(self.handle);
+        display.select_raw_motion(true);
+        display.center_pointer(self.handle, None);
+        Ok(())
+    }
+
+    pub fn has_pointer_lock(&self) -> bool
+    {
+        self.display.pointer_lock.get() == self.handle
+    }
+
+    // None goes back to the parent's cursor. remembered while the cursor is hidden
+    pub fn set_cursor(&self, cursor: Option<CursorId>)
+    {
+        self.data.cursor.set(cursor.map_or(0, |c| c.0));
+        if !self.data.cursor_hidden.get()
+        {
+            self.define_cursor(self.data.cursor.get());
+        }
+    }
+
+    pub fn set_cursor_visible(&self, visible: bool)
+    {
+        self.data.cursor_hidden.set(!visible);
+        let cursor = if visible { self.data.cursor.get() } else { self.display.blank_cursor() };
+        self.define_cursor(cursor);
+    }
+
+    fn define_cursor(&self, cursor: xlib::Cursor)
+    {
+        unsafe
+        {
+            if cursor == 0
+            {
+                xlib::XUndefineCursor(self.display.handle, self.handle);
+            }
+            else
+            {
+                xlib::XDefineCursor(self.display.handle, self.handle, cursor);
+            }
+        }
     }
 
     pub fn draw(&self) -> glengine::DrawContext
@@ -856,12 +1922,53 @@ impl<'a> XWindow<'a>
     }
 }
 
-impl<'a> Drop for XWindow<'a>
+// core and XI2 grabs share the status codes
+fn grab_result(status: c_int, request: &'static str) -> Result<(), Error>
+{
+    match status {
+        xlib::GrabSuccess => Ok(()),
+        xlib::AlreadyGrabbed => Err(Error::Grab(GrabError::AlreadyGrabbed)),
+        xlib::GrabInvalidTime => Err(Error::Grab(GrabError::InvalidTime)),
+        xlib::GrabNotViewable => Err(Error::Grab(GrabError::NotViewable)),
+        xlib::GrabFrozen => Err(Error::Grab(GrabError::Frozen)),
+        _ => Err(Error::XRequest(request)),
+    }
+}
+
+// an active keyboard or pointer grab, released when dropped
+pub struct DeviceGrab<'a, U: 'a = ()>
+{
+    display: &'a XDisplay<U>,
+    device_id: i32,
+}
+
+impl<'a, U> Drop for DeviceGrab<'a, U>
+{
+    fn drop(&mut self)
+    {
+        if self.display.pointer_grab.get() == self.device_id
+        {
+            self.display.pointer_grab.set(0);
+        }
+        unsafe
+        {
+            xinput2::XIUngrabDevice(self.display.handle, self.device_id, xlib::CurrentTime);
+            xlib::XFlush(self.display.handle);
+        }
+    }
+}
+
+impl<'a, U> Drop for XWindow<'a, U>
 {
     fn drop(&mut self)
     {
+        let _ = self.set_pointer_lock(false);
         unsafe
         {
+            if !self.data.ic.get().is_null()
+            {
+                xlib::XDestroyIC(self.data.ic.get());
+            }
             xlib::XDestroyWindow(self.display.handle, self.handle);
         }
     }
@@ -1,7 +1,7 @@
 extern crate x11test;
 extern crate rand;
 extern crate array_ext;
-use x11test::{XDisplay, Event, EvState, Key, Button};
+use x11test::{XDisplay, Event, EvState, Key, Button, Repeat};
 use rand::Rng;
 use array_ext::*;
 
@@ -21,29 +21,42 @@ fn main()
         255, 255, 255, 255,
     ]);
 
+    // brush outline cursor
+    let ring: Vec<u8> = (0 .. 16 * 16).flat_map(|i| {
+        let (x, y) = ((i % 16) as f32 - 7.5, (i / 16) as f32 - 7.5);
+        if ((x * x + y * y).sqrt() - 6.0).abs() < 1.0 { [255; 4] } else { [0; 4] }
+    }).collect();
+    window.set_cursor(display.create_cursor(16, 16, &ring, (8, 8)).ok());
+
     let mut others = Vec::new();
     let mut n = 1;
     let mut mdown = false;
+    let mut locked = false;
 
     'main: loop
     {
         // get and store event
-        display.wait_event();
+        display.wait_event().unwrap();
+
+        while let Some((ev, _)) = display.consume_event()
+        {
+            println!(">> display: {:?}", ev);
+        }
 
         // pull events for this window
-        while let Some(ev) = window.consume_event()
+        while let Some((ev, _)) = window.consume_event()
         {
             match ev
             {
-                Event::CloseButton | Event::Keyboard(EvState::Pressed, Key::Escape) => break 'main,
-                Event::Keyboard(EvState::Pressed, Key::Insert) => {
+                Event::CloseButton | Event::Keyboard(EvState::Pressed, Key::Escape, _, _, _) => break 'main,
+                Event::Keyboard(EvState::Pressed, Key::Insert, _, _, Repeat(false)) => {
                     let win = display.create_window(150, 150).unwrap();
                     win.set_title("child");
                     win.show();
                     others.push((n, win));
                     n += 1;
                 }
-                Event::Keyboard(EvState::Pressed, Key::Q) => {
+                Event::Keyboard(EvState::Pressed, Key::Q, _, _, _) => {
                     let size = { let s = window.get_size(); [s.0, s.1] };
                     let mut rng = rand::thread_rng();
                     let ctx = window.draw();
@@ -54,7 +67,7 @@ fn main()
                             rng.gen::<[f32; 4]>());
                     }
                 }
-                Event::Keyboard(EvState::Pressed, Key::W) => {
+                Event::Keyboard(EvState::Pressed, Key::W, _, _, _) => {
                     let size = { let s = window.get_size(); [s.0, s.1] };
                     let mut rng = rand::thread_rng();
                     let ctx = window.draw();
@@ -66,25 +79,46 @@ fn main()
                             rng.gen::<[f32; 4]>());
                     }
                 }
-                Event::Keyboard(EvState::Pressed, Key::Unk(ks)) => {
-                    println!("** keysym: {:x}", ks);
+                Event::Keyboard(EvState::Pressed, Key::L, _, _, Repeat(false)) => {
+                    locked = !locked;
+                    if let Err(err) = window.set_pointer_lock(locked)
+                    {
+                        println!("** pointer lock: {}", err);
+                        locked = false;
+                    }
+                }
+                Event::MouseDelta(dx, dy) => {
+                    println!(">> delta: {} {}", dx, dy);
+                }
+                Event::Keyboard(EvState::Pressed, Key::Unk(ks), sc, mods, _) => {
+                    println!("** keysym: {:x} {:?} {:?}", ks, sc, mods);
                 }
                 Event::Redraw => {
                     let ctx = window.draw();
                     ctx.clear([0.1, 0.1, 0.1, 1.0]);
                 }
-                Event::MouseButton(EvState::Pressed, Button::Left, (x, y)) => {
+                Event::MouseButton(EvState::Pressed, Button::Left, (x, y), _) => {
                     mdown = true;
                     let ctx = window.draw();
                     ctx.draw_rect([x as i16 - 5, y as i16 - 5], 10, 10, &tex);
                 }
-                Event::MouseButton(EvState::Released, Button::Left, _) => {
+                Event::MouseButton(EvState::Released, Button::Left, _, _) => {
                     mdown = false;
                 }
                 Event::MouseMoved(x, y) if mdown => {
                     let ctx = window.draw();
                     ctx.draw_rect([x as i16 - 5, y as i16 - 5], 10, 10, &tex);
                 }
+                Event::Touch(_, _, (x, y), _) => {
+                    let ctx = window.draw();
+                    ctx.draw_rect([x as i16 - 5, y as i16 - 5], 10, 10, &tex);
+                }
+                Event::Text(text) => {
+                    println!(">> text: {:?}", text);
+                }
+                Event::PreeditUpdate(text, cursor) => {
+                    println!(">> preedit: {:?} at {}", text, cursor);
+                }
                 //_ => println!(">> main: {:?}", ev)
                 _ => ()
             }
@@ -92,7 +126,7 @@ fn main()
 
         // handling a bunch of windows
         others.retain(|&(id, ref win)| {
-            while let Some(ev) = win.consume_event()
+            while let Some((ev, _)) = win.consume_event()
             {
                 match ev
                 {
@@ -0,0 +1,117 @@
+// lets other threads post events and wake up the thread waiting on XDisplay
+use libc;
+use x11::xlib;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use error::Error;
+
+// shared between the display and all the proxies, so the pipe lives until the last one is gone
+pub struct ProxyShared<U>
+{
+    queue: Mutex<VecDeque<(Option<xlib::Window>, U)>>,
+    closed: AtomicBool,
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl<U> ProxyShared<U>
+{
+    pub fn new() -> Result<Arc<Self>, Error>
+    {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0
+        {
+            return Err(Error::Os("pipe2", io::Error::last_os_error().raw_os_error().unwrap_or(0)))
+        }
+        Ok(Arc::new(ProxyShared{
+            queue: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            read_fd: fds[0],
+            write_fd: fds[1],
+        }))
+    }
+
+    pub fn read_fd(&self) -> RawFd
+    {
+        self.read_fd
+    }
+
+    // empties the pipe and returns everything that was posted
+    pub fn take_events(&self) -> VecDeque<(Option<xlib::Window>, U)>
+    {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.read_fd, buf.as_mut_ptr() as *mut _, buf.len()) } > 0 {}
+        let mut queue = self.queue.lock().unwrap();
+        queue.drain(..).collect()
+    }
+
+    // called when the display goes away, posting fails from now on
+    pub fn close(&self)
+    {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    fn post(&self, target: Option<xlib::Window>, payload: U) -> Result<(), U>
+    {
+        if self.closed.load(Ordering::SeqCst)
+        {
+            return Err(payload)
+        }
+        self.queue.lock().unwrap().push_back((target, payload));
+        // a full pipe already guarantees a wakeup, so the result doesn't matter
+        unsafe { libc::write(self.write_fd, b"\0".as_ptr() as *const _, 1) };
+        Ok(())
+    }
+}
+
+impl<U> Drop for ProxyShared<U>
+{
+    fn drop(&mut self)
+    {
+        unsafe
+        {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+// identifies a window across threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowId(pub(crate) xlib::Window);
+
+pub struct EventProxy<U>
+{
+    shared: Arc<ProxyShared<U>>,
+}
+
+impl<U> EventProxy<U>
+{
+    pub(crate) fn new(shared: Arc<ProxyShared<U>>) -> Self
+    {
+        EventProxy{ shared }
+    }
+
+    // posts Event::User into the window queue, gives the payload back if the display is gone
+    pub fn send_event(&self, win: WindowId, payload: U) -> Result<(), U>
+    {
+        self.shared.post(Some(win.0), payload)
+    }
+
+    // same, but the event ends in the display queue as DisplayEvent::User
+    pub fn send_display_event(&self, payload: U) -> Result<(), U>
+    {
+        self.shared.post(None, payload)
+    }
+}
+
+impl<U> Clone for EventProxy<U>
+{
+    fn clone(&self) -> Self
+    {
+        EventProxy{ shared: self.shared.clone() }
+    }
+}
@@ -0,0 +1,370 @@
+// text format for recording and replaying event streams. one event per line:
+//   <server time in ms> <event name> [args...]
+// Event::User and Event::Timer are generated by the application, so they aren't recorded
+use event::*;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::char;
+
+// version 2 added the scancode to Keyboard
+const HEADER_PREFIX: &str = "x11test-events ";
+const VERSION: u32 = 2;
+
+pub fn write_header<W: Write>(w: &mut W) -> io::Result<()>
+{
+    writeln!(w, "{}{}", HEADER_PREFIX, VERSION)
+}
+
+// returns false if the event can't be recorded
+pub fn write_event<W: Write, U>(w: &mut W, ev: &Event<U>, time: Timestamp) -> io::Result<bool>
+{
+    let line = match *ev {
+        Event::Keyboard(state, key, scancode, mods, Repeat(repeat)) => format!("Keyboard {} {} {} {}{}",
+            state_str(state), key_str(key), scancode_str(scancode), mods_str(mods), if repeat { " repeat" } else { "" }),
+        Event::Text(ref text) => format!("Text {}", text_str(text)),
+        Event::PreeditStart => "PreeditStart".into(),
+        // the text goes last, it can be empty
+        Event::PreeditUpdate(ref text, cursor) => format!("PreeditUpdate {} {}", cursor, text_str(text)),
+        Event::PreeditEnd => "PreeditEnd".into(),
+        Event::MouseButton(state, button, (x, y), mods) => format!("MouseButton {} {} {} {} {}", state_str(state), button_str(button), x, y, mods_str(mods)),
+        Event::MouseMoved(x, y) => format!("MouseMoved {} {}", x, y),
+        Event::MouseDelta(x, y) => format!("MouseDelta {} {}", x, y),
+        Event::AxisMoved(AxisState::Scroll(x, y)) => format!("AxisMoved Scroll {} {}", x, y),
+        Event::AxisMoved(AxisState::Pressure(p)) => format!("AxisMoved Pressure {}", p),
+        Event::AxisMoved(AxisState::Tilt(x, y)) => format!("AxisMoved Tilt {} {}", x, y),
+        Event::Touch(state, id, (x, y), pressure) => format!("Touch {} {} {} {} {}", touch_state_str(state), id, x, y, pressure),
+        Event::PointerInside(inside) => format!("PointerInside {}", inside),
+        Event::Resized(w, h) => format!("Resized {} {}", w, h),
+        Event::Moved(x, y) => format!("Moved {} {}", x, y),
+        Event::Focused(focused) => format!("Focused {}", focused),
+        Event::Redraw => "Redraw".into(),
+        Event::CloseButton => "CloseButton".into(),
+        Event::Timer(_) | Event::User(_) => return Ok(false),
+    };
+    writeln!(w, "{} {}", time.0, line)?;
+    Ok(true)
+}
+
+pub fn read_events<R: BufRead, U>(r: R) -> io::Result<Vec<(Event<U>, Timestamp)>>
+{
+    let mut lines = r.lines();
+    let version = match lines.next() {
+        Some(Ok(header)) => header.strip_prefix(HEADER_PREFIX).and_then(|v| v.parse::<u32>().ok()),
+        Some(Err(e)) => return Err(e),
+        None => None,
+    };
+    let version = match version {
+        Some(v) if (1 ..= VERSION).contains(&v) => v,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not an event recording, or an unsupported version")),
+    };
+
+    let mut events = Vec::new();
+    for (num, line) in lines.enumerate()
+    {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+        match parse_line(&line, version) {
+            Some(ev) => events.push(ev),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: invalid event: {}", num + 2, line))),
+        }
+    }
+    Ok(events)
+}
+
+fn parse_line<U>(line: &str, version: u32) -> Option<(Event<U>, Timestamp)>
+{
+    let mut args = line.split_whitespace();
+    let time = Timestamp(parse(args.next())?);
+
+    let ev = match args.next()? {
+        "Keyboard" => {
+            let state = parse_state(args.next())?;
+            let key = parse_key(args.next()?)?;
+            let scancode = if version >= 2 { parse_scancode(args.next()?)? } else { ScanCode::Unk(0) };
+            let mods = parse_mods(args.next())?;
+            let repeat = match args.next() {
+                Some("repeat") => true,
+                Some(_) => return None,
+                None => false,
+            };
+            Event::Keyboard(state, key, scancode, mods, Repeat(repeat))
+        },
+        "Text" => Event::Text(parse_text(args.next()?)?),
+        "PreeditStart" => Event::PreeditStart,
+        "PreeditUpdate" => {
+            let cursor = parse(args.next())?;
+            Event::PreeditUpdate(parse_text(args.next().unwrap_or(""))?, cursor)
+        },
+        "PreeditEnd" => Event::PreeditEnd,
+        "MouseButton" => {
+            let state = parse_state(args.next())?;
+            let button = parse_button(args.next()?)?;
+            let pos = (parse(args.next())?, parse(args.next())?);
+            Event::MouseButton(state, button, pos, parse_mods(args.next())?)
+        },
+        "MouseMoved" => Event::MouseMoved(parse(args.next())?, parse(args.next())?),
+        "MouseDelta" => Event::MouseDelta(parse(args.next())?, parse(args.next())?),
+        "AxisMoved" => Event::AxisMoved(match args.next()? {
+            "Scroll" => AxisState::Scroll(parse(args.next())?, parse(args.next())?),
+            "Pressure" => AxisState::Pressure(parse(args.next())?),
+            "Tilt" => AxisState::Tilt(parse(args.next())?, parse(args.next())?),
+            _ => return None,
+        }),
+        "Touch" => {
+            let state = parse_touch_state(args.next())?;
+            let id = parse(args.next())?;
+            let pos = (parse(args.next())?, parse(args.next())?);
+            Event::Touch(state, id, pos, parse(args.next())?)
+        },
+        "PointerInside" => Event::PointerInside(parse(args.next())?),
+        "Resized" => Event::Resized(parse(args.next())?, parse(args.next())?),
+        "Moved" => Event::Moved(parse(args.next())?, parse(args.next())?),
+        "Focused" => Event::Focused(parse(args.next())?),
+        "Redraw" => Event::Redraw,
+        "CloseButton" => Event::CloseButton,
+        _ => return None,
+    };
+
+    // trailing garbage means we're reading something we don't understand
+    if args.next().is_some() { return None }
+    Some((ev, time))
+}
+
+fn parse<T: FromStr>(arg: Option<&str>) -> Option<T>
+{
+    arg.and_then(|s| s.parse().ok())
+}
+
+fn state_str(state: EvState) -> &'static str
+{
+    match state {
+        EvState::Pressed => "Pressed",
+        EvState::Released => "Released",
+    }
+}
+
+fn parse_state(arg: Option<&str>) -> Option<EvState>
+{
+    match arg? {
+        "Pressed" => Some(EvState::Pressed),
+        "Released" => Some(EvState::Released),
+        _ => None,
+    }
+}
+
+fn touch_state_str(state: TouchState) -> &'static str
+{
+    match state {
+        TouchState::Begin => "Begin",
+        TouchState::Update => "Update",
+        TouchState::End => "End",
+    }
+}
+
+fn parse_touch_state(arg: Option<&str>) -> Option<TouchState>
+{
+    match arg? {
+        "Begin" => Some(TouchState::Begin),
+        "Update" => Some(TouchState::Update),
+        "End" => Some(TouchState::End),
+        _ => None,
+    }
+}
+
+fn button_str(button: Button) -> String
+{
+    match button {
+        Button::Left => "Left".into(),
+        Button::Middle => "Middle".into(),
+        Button::Right => "Right".into(),
+        Button::Other(n) => format!("Other:{}", n),
+    }
+}
+
+fn parse_button(arg: &str) -> Option<Button>
+{
+    match arg {
+        "Left" => Some(Button::Left),
+        "Middle" => Some(Button::Middle),
+        "Right" => Some(Button::Right),
+        _ => arg.strip_prefix("Other:").and_then(|n| n.parse().ok()).map(Button::Other),
+    }
+}
+
+// names joined by '+', or '-' when empty
+fn mods_str(mods: Modifiers) -> String
+{
+    if mods.is_empty() { return "-".into() }
+    let names: Vec<_> = Modifiers::NAMES.iter()
+        .filter(|&&(m, _)| mods.contains(m))
+        .map(|&(_, name)| name).collect();
+    names.join("+")
+}
+
+// recordings made before modifiers existed don't have them
+fn parse_mods(arg: Option<&str>) -> Option<Modifiers>
+{
+    let arg = match arg {
+        None | Some("-") => return Some(Modifiers::empty()),
+        Some(arg) => arg,
+    };
+    let mut mods = Modifiers::empty();
+    for name in arg.split('+')
+    {
+        mods |= Modifiers::NAMES.iter().find(|&&(_, n)| n == name)?.0;
+    }
+    Some(mods)
+}
+
+// the text is a single word: whitespace, backslashes and anything outside printable ascii become \u{hex}
+fn text_str(text: &str) -> String
+{
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars()
+    {
+        if c.is_ascii_graphic() && c != '\\'
+        {
+            s.push(c);
+        }
+        else
+        {
+            s.push_str(&format!("\\u{{{:x}}}", c as u32));
+        }
+    }
+    s
+}
+
+fn parse_text(arg: &str) -> Option<String>
+{
+    let mut text = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(pos) = rest.find('\\')
+    {
+        text.push_str(&rest[.. pos]);
+        let escape = rest[pos ..].strip_prefix("\\u{")?;
+        let end = escape.find('}')?;
+        text.push(u32::from_str_radix(&escape[.. end], 16).ok().and_then(char::from_u32)?);
+        rest = &escape[end + 1 ..];
+    }
+    text.push_str(rest);
+    Some(text)
+}
+
+fn key_str(key: Key) -> String
+{
+    match key {
+        Key::Unk(keysym) => format!("Unk:0x{:x}", keysym),
+        _ => key.name().unwrap().into(),
+    }
+}
+
+fn parse_key(arg: &str) -> Option<Key>
+{
+    match arg.strip_prefix("Unk:0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(Key::Unk),
+        None => Key::from_name(arg),
+    }
+}
+
+fn scancode_str(scancode: ScanCode) -> String
+{
+    match scancode {
+        ScanCode::Unk(keycode) => format!("Unk:{}", keycode),
+        _ => scancode.name().unwrap().into(),
+    }
+}
+
+fn parse_scancode(arg: &str) -> Option<ScanCode>
+{
+    match arg.strip_prefix("Unk:") {
+        Some(keycode) => keycode.parse().ok().map(ScanCode::Unk),
+        None => ScanCode::from_name(arg),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn round_trip(events: &[(Event, Timestamp)]) -> Vec<(Event, Timestamp)>
+    {
+        let mut buf = Vec::new();
+        write_header(&mut buf).unwrap();
+        for &(ref ev, time) in events
+        {
+            assert!(write_event(&mut buf, ev, time).unwrap());
+        }
+        read_events(&buf[..]).unwrap()
+    }
+
+    #[test]
+    fn every_variant()
+    {
+        let events = vec![
+            Event::Keyboard(EvState::Pressed, Key::A, ScanCode::Key1, Modifiers::CTRL | Modifiers::SHIFT, Repeat(false)),
+            Event::Keyboard(EvState::Pressed, Key::Unk(0x1234), ScanCode::Unk(250), Modifiers::empty(), Repeat(true)),
+            Event::Keyboard(EvState::Released, Key::F12, ScanCode::Key2, Modifiers::NUM_LOCK, Repeat(false)),
+            Event::Text("a b\\c\u{e9}\u{1f600}\n".into()),
+            Event::PreeditStart,
+            Event::PreeditUpdate("\u{304b}\u{306a} x".into(), 2),
+            Event::PreeditUpdate(String::new(), 0),
+            Event::PreeditEnd,
+            Event::MouseButton(EvState::Pressed, Button::Left, (1.5, -2.0), Modifiers::ALT),
+            Event::MouseButton(EvState::Released, Button::Other(9), (0.0, 0.0), Modifiers::empty()),
+            Event::MouseMoved(10.25, 20.0),
+            Event::MouseDelta(-0.5, 3.0),
+            Event::AxisMoved(AxisState::Scroll(0.0, -1.0)),
+            Event::AxisMoved(AxisState::Pressure(0.75)),
+            Event::AxisMoved(AxisState::Tilt(-0.25, 0.5)),
+            Event::Touch(TouchState::Begin, 7, (100.0, 50.5), 1.0),
+            Event::Touch(TouchState::Update, 7, (101.0, 51.0), 0.5),
+            Event::Touch(TouchState::End, 7, (101.0, 51.0), 0.0),
+            Event::PointerInside(true),
+            Event::Resized(640, 480),
+            Event::Moved(-10, 20),
+            Event::Focused(false),
+            Event::Redraw,
+            Event::CloseButton,
+        ];
+        let events: Vec<_> = events.into_iter().zip(1000..).map(|(ev, t)| (ev, Timestamp(t))).collect();
+        assert_eq!(round_trip(&events), events);
+    }
+
+    #[test]
+    fn text_escapes()
+    {
+        assert_eq!(text_str("a b\\"), "a\\u{20}b\\u{5c}");
+        assert_eq!(parse_text("a\\u{20}b\\u{5c}").unwrap(), "a b\\");
+        assert_eq!(parse_text("\\u{110000}"), None);
+        assert_eq!(parse_text("\\u{20"), None);
+    }
+
+    #[test]
+    fn app_events_not_recorded()
+    {
+        let mut buf = Vec::new();
+        assert!(!write_event(&mut buf, &Event::Timer::<()>(TimerId(1)), Timestamp(0)).unwrap());
+        assert!(!write_event(&mut buf, &Event::User(5), Timestamp(0)).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn old_versions()
+    {
+        let data = "x11test-events 1\n5 Keyboard Pressed A Ctrl repeat\n6 MouseMoved 1 2\n";
+        let events: Vec<(Event, _)> = read_events(data.as_bytes()).unwrap();
+        assert_eq!(events, vec![
+            (Event::Keyboard(EvState::Pressed, Key::A, ScanCode::Unk(0), Modifiers::CTRL, Repeat(true)), Timestamp(5)),
+            (Event::MouseMoved(1.0, 2.0), Timestamp(6)),
+        ]);
+    }
+
+    #[test]
+    fn invalid()
+    {
+        assert!(read_events::<_, ()>("x11test-events 99\n".as_bytes()).is_err());
+        assert!(read_events::<_, ()>("x11test-events 2\n1 Redraw extra\n".as_bytes()).is_err());
+        assert!(read_events::<_, ()>("x11test-events 2\n1 Bogus\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+// human readable keys and shortcuts like "Ctrl+Shift+F5", for config files.
+// key names are the X keysym names (XKeysymToString), with single letters in uppercase
+use x11::xlib;
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+use std::ffi::{CStr, CString};
+use event::*;
+use translate::convert_keysym;
+
+// convert_keysym maps some keys from more than one keysym, so the ranges are in order of preference
+const KEYSYM_RANGES: [(u32, u32); 4] = [(0xff00, 0xffff), (0xfe00, 0xfeff), (0x20, 0xff), (0x1008ff00, 0x1008ffff)];
+
+// the inverse of convert_keysym
+pub fn key_to_keysym(key: Key) -> Option<u32>
+{
+    if let Key::Unk(keysym) = key
+    {
+        return Some(keysym)
+    }
+    KEYSYM_RANGES.iter().flat_map(|&(first, last)| first ..= last).find(|&ks| convert_keysym(ks) == key)
+}
+
+pub fn key_to_string(key: Key) -> String
+{
+    let keysym = match key_to_keysym(key) {
+        Some(ks) => ks,
+        None => return key.name().unwrap_or_default().into(),
+    };
+    // points to a static table, not freed
+    let name = unsafe { xlib::XKeysymToString(keysym as xlib::KeySym) };
+    if name.is_null()
+    {
+        return format!("0x{:x}", keysym)
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    if name.len() == 1 { name.to_uppercase() } else { name.into_owned() }
+}
+
+// accepts keysym names (case insensitive for letters), 0x<keysym> and the Key variant names
+pub fn key_from_string(name: &str) -> Option<Key>
+{
+    if let Some(hex) = name.strip_prefix("0x")
+    {
+        return u32::from_str_radix(hex, 16).ok().map(convert_keysym)
+    }
+
+    let cname = CString::new(name).ok()?;
+    let keysym = unsafe { xlib::XStringToKeysym(cname.as_ptr()) };
+    if keysym != 0
+    {
+        return match convert_keysym(keysym as u32) {
+            // keys are stored by the lowercase keysym
+            Key::Unk(_) => {
+                let (mut lower, mut upper) = (0, 0);
+                unsafe { xlib::XConvertCase(keysym, &mut lower, &mut upper) };
+                Some(convert_keysym(lower as u32))
+            },
+            key => Some(key),
+        }
+    }
+    Key::from_name(name)
+}
+
+fn modifier_from_string(name: &str) -> Option<Modifiers>
+{
+    if name.eq_ignore_ascii_case("Control")
+    {
+        return Some(Modifiers::CTRL)
+    }
+    Modifiers::NAMES.iter().find(|&&(_, n)| n.eq_ignore_ascii_case(name)).map(|&(m, _)| m)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shortcut
+{
+    pub mods: Modifiers,
+    pub key: Key,
+}
+
+impl Shortcut
+{
+    pub fn new(mods: Modifiers, key: Key) -> Self
+    {
+        Shortcut{ mods, key }
+    }
+
+    // CapsLock and NumLock don't get in the way, unless the shortcut asks for them
+    pub fn matches(&self, key: Key, mods: Modifiers) -> bool
+    {
+        self.key == key && mods.without_locks() == self.mods.without_locks() && mods.contains(self.mods)
+    }
+}
+
+impl fmt::Display for Shortcut
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        for &(m, name) in Modifiers::NAMES.iter()
+        {
+            if self.mods.contains(m)
+            {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "{}", key_to_string(self.key))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseShortcutError(pub String /* the part that failed */);
+
+impl fmt::Display for ParseShortcutError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "invalid key or modifier: {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseShortcutError {}
+
+impl FromStr for Shortcut
+{
+    type Err = ParseShortcutError;
+
+    // the last part is the key, use "plus" for the + key
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut parts: Vec<_> = s.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or("");
+        let key = key_from_string(key_name).ok_or_else(|| ParseShortcutError(key_name.into()))?;
+
+        let mut mods = Modifiers::empty();
+        for name in parts
+        {
+            mods |= modifier_from_string(name).ok_or_else(|| ParseShortcutError(name.into()))?;
+        }
+        Ok(Shortcut{ mods, key })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(s: &str) -> Result<Shortcut, ParseShortcutError>
+    {
+        s.parse()
+    }
+
+    #[test]
+    fn plus_key()
+    {
+        assert_eq!(parse("Ctrl++"), Err(ParseShortcutError("".into())));
+        assert_eq!(parse("Ctrl+plus"), Ok(Shortcut::new(Modifiers::CTRL, Key::Plus)));
+        assert_eq!(Shortcut::new(Modifiers::CTRL, Key::Plus).to_string(), "Ctrl+plus");
+    }
+
+    #[test]
+    fn letter_case()
+    {
+        assert_eq!(key_from_string("Q"), Some(Key::Q));
+        assert_eq!(key_from_string("q"), Some(Key::Q));
+        assert_eq!(parse("control+shift+Q"), Ok(Shortcut::new(Modifiers::CTRL | Modifiers::SHIFT, Key::Q)));
+        assert_eq!(key_to_string(Key::Q), "Q");
+    }
+
+    #[test]
+    fn keysym_numbers()
+    {
+        assert_eq!(key_from_string("0xff52"), Some(Key::Up));
+        assert_eq!(key_from_string("0x1234567"), Some(Key::Unk(0x1234567)));
+        assert_eq!(key_from_string("0xzz"), None);
+        // keysyms without a name
+        assert_eq!(key_to_string(Key::Unk(0x1234567)), "0x1234567");
+    }
+
+    #[test]
+    fn invalid()
+    {
+        assert_eq!(parse("Ctrl+Nope"), Err(ParseShortcutError("Nope".into())));
+        assert_eq!(parse("Hyper+A"), Err(ParseShortcutError("Hyper".into())));
+        assert_eq!(parse(""), Err(ParseShortcutError("".into())));
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let shortcuts = [
+            Shortcut::new(Modifiers::empty(), Key::A),
+            Shortcut::new(Modifiers::CTRL | Modifiers::ALT, Key::Z),
+            Shortcut::new(Modifiers::SUPER, Key::Key5),
+            Shortcut::new(Modifiers::SHIFT, Key::F5),
+            Shortcut::new(Modifiers::CTRL, Key::NumpadAdd),
+            Shortcut::new(Modifiers::NUM_LOCK, Key::NumpadEnter),
+            Shortcut::new(Modifiers::ALT, Key::Up),
+            Shortcut::new(Modifiers::CTRL, Key::Unk(0x1234567)),
+            Shortcut::new(Modifiers::ALT | Modifiers::CAPS_LOCK, Key::Unk(0x6c6 /* Cyrillic_ef */)),
+        ];
+        for s in shortcuts.iter()
+        {
+            assert_eq!(parse(&s.to_string()).as_ref(), Ok(s), "{}", s);
+        }
+    }
+
+    #[test]
+    fn matching()
+    {
+        let s = Shortcut::new(Modifiers::CTRL, Key::S);
+        assert!(s.matches(Key::S, Modifiers::CTRL | Modifiers::NUM_LOCK | Modifiers::CAPS_LOCK));
+        assert!(!s.matches(Key::S, Modifiers::CTRL | Modifiers::SHIFT));
+        assert!(!Shortcut::new(Modifiers::NUM_LOCK, Key::A).matches(Key::A, Modifiers::empty()));
+    }
+}
@@ -0,0 +1,1105 @@
+// translation from raw X event structs to our Event type. this doesn't call into Xlib,
+// keysyms come from a Keymap and the axis state lives in InputState, so it can run without a server
+use x11::{xlib, xinput2, keysym};
+use std::slice;
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_uint;
+use event::*;
+
+// keycode -> keysym lookup, implemented by XDisplay on top of XKB
+pub trait Keymap
+{
+    // group is the active layout, level the shift level (0 is the base one)
+    fn keycode_to_keysym(&self, keycode: xlib::KeyCode, group: u8, level: u8) -> xlib::KeySym;
+
+    fn modifier_map(&self) -> ModifierMap
+    {
+        ModifierMap::default()
+    }
+}
+
+// with XKB, core events have the group in bits 13-14 of the state
+pub fn group_from_state(state: c_uint) -> u8
+{
+    ((state >> 13) & 3) as u8
+}
+
+// the group a key uses when the active one is past the groups it has, like XkbTranslateKeyCode does.
+// group_info is the XkbSymMapRec field: the number of groups and what to do with the out of range ones.
+// None if the key has no groups
+pub fn effective_group(group_info: u8, group: u8) -> Option<u8>
+{
+    let num_groups = group_info & 0x0f;
+    if num_groups == 0 { return None }
+    if group < num_groups { return Some(group) }
+    match group_info & 0xc0 {
+        // XkbRedirectIntoRange
+        0x80 => {
+            let redirect = (group_info >> 4) & 0x03;
+            Some(if redirect < num_groups { redirect } else { 0 })
+        },
+        // XkbClampIntoRange
+        0x40 => Some(num_groups - 1),
+        // XkbWrapIntoRange
+        _ => Some(group % num_groups),
+    }
+}
+
+// which of the Mod1-Mod5 bits mean Alt, Super and NumLock, that depends on the server modifier mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModifierMap
+{
+    pub alt: c_uint,
+    pub super_: c_uint,
+    pub num_lock: c_uint,
+}
+
+impl Default for ModifierMap
+{
+    // the usual xkb setup
+    fn default() -> Self
+    {
+        ModifierMap{ alt: xlib::Mod1Mask, super_: xlib::Mod4Mask, num_lock: xlib::Mod2Mask }
+    }
+}
+
+impl ModifierMap
+{
+    // from the keysyms bound to each modifier index, as given by XGetModifierMapping
+    pub fn from_keysyms(mod_keysyms: &[(usize /* modifier index */, xlib::KeySym)]) -> Self
+    {
+        let mut map = ModifierMap{ alt: 0, super_: 0, num_lock: 0 };
+        for &(index, ks) in mod_keysyms
+        {
+            // Shift, Lock and Control have fixed meanings
+            if index < 3 { continue }
+            let mask = 1 << index;
+            match ks as u32 {
+                keysym::XK_Alt_L | keysym::XK_Alt_R | keysym::XK_Meta_L | keysym::XK_Meta_R => map.alt |= mask,
+                keysym::XK_Super_L | keysym::XK_Super_R => map.super_ |= mask,
+                keysym::XK_Num_Lock => map.num_lock |= mask,
+                _ => (),
+            }
+        }
+        map
+    }
+
+    // converts the state field of core and XI2 events
+    pub fn convert(&self, state: c_uint) -> Modifiers
+    {
+        let mut mods = Modifiers::empty();
+        if state & xlib::ShiftMask != 0 { mods |= Modifiers::SHIFT }
+        if state & xlib::ControlMask != 0 { mods |= Modifiers::CTRL }
+        if state & xlib::LockMask != 0 { mods |= Modifiers::CAPS_LOCK }
+        if state & self.alt != 0 { mods |= Modifiers::ALT }
+        if state & self.super_ != 0 { mods |= Modifiers::SUPER }
+        if state & self.num_lock != 0 { mods |= Modifiers::NUM_LOCK }
+        mods
+    }
+
+    // the inverse of convert, for requests that take a modifier mask
+    pub fn to_state(self, mods: Modifiers) -> c_uint
+    {
+        let mut state = 0;
+        if mods.contains(Modifiers::SHIFT) { state |= xlib::ShiftMask }
+        if mods.contains(Modifiers::CTRL) { state |= xlib::ControlMask }
+        if mods.contains(Modifiers::CAPS_LOCK) { state |= xlib::LockMask }
+        if mods.contains(Modifiers::ALT) { state |= self.alt }
+        if mods.contains(Modifiers::SUPER) { state |= self.super_ }
+        if mods.contains(Modifiers::NUM_LOCK) { state |= self.num_lock }
+        state
+    }
+}
+
+fn as_button(button: i32) -> Button
+{
+    match button {
+        1 => Button::Left,
+        2 => Button::Middle,
+        3 => Button::Right,
+        other => Button::Other(other as u8)
+    }
+}
+
+// the buttons set in an XIButtonState mask, bit 0 isn't used
+pub fn pressed_buttons(mask: &[u8]) -> Vec<Button>
+{
+    (1 .. mask.len() as i32 * 8).filter(|&b| xinput2::XIMaskIsSet(mask, b)).map(as_button).collect()
+}
+
+fn as_axis_scroll(button: i32) -> AxisState
+{
+    match button {
+        4 => AxisState::Scroll(0.0, 1.0),
+        5 => AxisState::Scroll(0.0, -1.0),
+        6 => AxisState::Scroll(-1.0, 0.0),
+        7 => AxisState::Scroll(1.0, 0.0),
+        _ => unreachable!()
+    }
+}
+
+pub fn convert_keysym(keysym: u32) -> Key
+{
+    match keysym {
+        keysym::XK_0 => Key::Key0,
+        keysym::XK_1 => Key::Key1,
+        keysym::XK_2 => Key::Key2,
+        keysym::XK_3 => Key::Key3,
+        keysym::XK_4 => Key::Key4,
+        keysym::XK_5 => Key::Key5,
+        keysym::XK_6 => Key::Key6,
+        keysym::XK_7 => Key::Key7,
+        keysym::XK_8 => Key::Key8,
+        keysym::XK_9 => Key::Key9,
+
+        keysym::XK_a => Key::A,
+        keysym::XK_b => Key::B,
+        keysym::XK_c => Key::C,
+        keysym::XK_d => Key::D,
+        keysym::XK_e => Key::E,
+        keysym::XK_f => Key::F,
+        keysym::XK_g => Key::G,
+        keysym::XK_h => Key::H,
+        keysym::XK_i => Key::I,
+        keysym::XK_j => Key::J,
+        keysym::XK_k => Key::K,
+        keysym::XK_l => Key::L,
+        keysym::XK_m => Key::M,
+        keysym::XK_n => Key::N,
+        keysym::XK_o => Key::O,
+        keysym::XK_p => Key::P,
+        keysym::XK_q => Key::Q,
+        keysym::XK_r => Key::R,
+        keysym::XK_s => Key::S,
+        keysym::XK_t => Key::T,
+        keysym::XK_u => Key::U,
+        keysym::XK_v => Key::V,
+        keysym::XK_w => Key::W,
+        keysym::XK_x => Key::X,
+        keysym::XK_y => Key::Y,
+        keysym::XK_z => Key::Z,
+
+        keysym::XK_F1 => Key::F1,
+        keysym::XK_F2 => Key::F2,
+        keysym::XK_F3 => Key::F3,
+        keysym::XK_F4 => Key::F4,
+        keysym::XK_F5 => Key::F5,
+        keysym::XK_F6 => Key::F6,
+        keysym::XK_F7 => Key::F7,
+        keysym::XK_F8 => Key::F8,
+        keysym::XK_F9 => Key::F9,
+        keysym::XK_F10 => Key::F10,
+        keysym::XK_F11 => Key::F11,
+        keysym::XK_F12 => Key::F12,
+        keysym::XK_F13 => Key::F13,
+        keysym::XK_F14 => Key::F14,
+        keysym::XK_F15 => Key::F15,
+        keysym::XK_F16 => Key::F16,
+        keysym::XK_F17 => Key::F17,
+        keysym::XK_F18 => Key::F18,
+        keysym::XK_F19 => Key::F19,
+        keysym::XK_F20 => Key::F20,
+        keysym::XK_F21 => Key::F21,
+        keysym::XK_F22 => Key::F22,
+        keysym::XK_F23 => Key::F23,
+        keysym::XK_F24 => Key::F24,
+
+        keysym::XK_Escape => Key::Escape,
+        keysym::XK_BackSpace => Key::BackSpace,
+        keysym::XK_Tab => Key::Tab,
+        keysym::XK_ISO_Left_Tab => Key::Tab,
+        keysym::XK_Return => Key::Return,
+        keysym::XK_Caps_Lock => Key::CapsLock,
+        keysym::XK_Shift_L => Key::ShiftLeft,
+        keysym::XK_Shift_R => Key::ShiftRight,
+        keysym::XK_Control_L => Key::ControlLeft,
+        keysym::XK_Control_R => Key::ControlRight,
+        keysym::XK_Alt_L => Key::AltLeft,
+        keysym::XK_Alt_R => Key::AltRight,  // US only
+        keysym::XK_Super_L => Key::SuperLeft,
+        keysym::XK_Super_R => Key::SuperRight,
+        keysym::XK_Meta_L => Key::MetaLeft, // Mac's ⌘ key
+        keysym::XK_Meta_R => Key::MetaRight,
+        keysym::XK_Mode_switch => Key::ModeSwitch,  // Mac's ⌥ key
+        keysym::XK_ISO_Level5_Shift => Key::Level5Shift,
+        keysym::XK_ISO_Next_Group => Key::NextGroup,
+        keysym::XK_Multi_key => Key::Compose,
+        keysym::XK_Menu => Key::Menu,
+        keysym::XK_Help => Key::Help,
+        keysym::XK_space => Key::Space,
+
+        keysym::XK_Print => Key::Print,
+        keysym::XK_Scroll_Lock => Key::ScrollLock,
+        keysym::XK_Pause => Key::Pause,
+        keysym::XK_Insert => Key::Insert,
+        keysym::XK_Delete => Key::Delete,
+        keysym::XK_Home => Key::Home,
+        keysym::XK_End => Key::End,
+        keysym::XK_Page_Up => Key::PageUp,
+        keysym::XK_Page_Down => Key::PageDown,
+        keysym::XK_Up => Key::Up,
+        keysym::XK_Down => Key::Down,
+        keysym::XK_Right => Key::Right,
+        keysym::XK_Left => Key::Left,
+        keysym::XK_KP_0 => Key::Numpad0,
+        keysym::XK_KP_1 => Key::Numpad1,
+        keysym::XK_KP_2 => Key::Numpad2,
+        keysym::XK_KP_3 => Key::Numpad3,
+        keysym::XK_KP_4 => Key::Numpad4,
+        keysym::XK_KP_5 => Key::Numpad5,
+        keysym::XK_KP_6 => Key::Numpad6,
+        keysym::XK_KP_7 => Key::Numpad7,
+        keysym::XK_KP_8 => Key::Numpad8,
+        keysym::XK_KP_9 => Key::Numpad9,
+        // what the numpad sends with NumLock off
+        keysym::XK_KP_Insert => Key::NumpadInsert,
+        keysym::XK_KP_End => Key::NumpadEnd,
+        keysym::XK_KP_Down => Key::NumpadDown,
+        keysym::XK_KP_Page_Down => Key::NumpadPageDown,
+        keysym::XK_KP_Left => Key::NumpadLeft,
+        keysym::XK_KP_Begin => Key::NumpadBegin,
+        keysym::XK_KP_Right => Key::NumpadRight,
+        keysym::XK_KP_Home => Key::NumpadHome,
+        keysym::XK_KP_Up => Key::NumpadUp,
+        keysym::XK_KP_Page_Up => Key::NumpadPageUp,
+        keysym::XK_KP_Delete => Key::NumpadDelete,
+
+        keysym::XK_Num_Lock => Key::NumLock,
+        keysym::XK_KP_Decimal => Key::NumpadDecimal,
+        keysym::XK_KP_Separator => Key::NumpadSeparator,
+        keysym::XK_KP_Equal => Key::NumpadEqual,
+        keysym::XK_KP_Add => Key::NumpadAdd,
+        keysym::XK_KP_Subtract => Key::NumpadSubtract,
+        keysym::XK_KP_Multiply => Key::NumpadMultiply,
+        keysym::XK_KP_Divide => Key::NumpadDivide,
+        keysym::XK_KP_Enter => Key::NumpadEnter,
+
+        keysym::XK_numbersign => Key::Numbersign,
+        keysym::XK_apostrophe => Key::Apostrophe,
+        keysym::XK_plus => Key::Plus,
+        keysym::XK_comma => Key::Comma,
+        keysym::XK_minus => Key::Minus,
+        keysym::XK_period => Key::Period,
+        keysym::XK_slash => Key::Slash,
+        keysym::XK_semicolon => Key::Semicolon,
+        keysym::XK_less => Key::Less,
+        keysym::XK_equal => Key::Equal,
+        keysym::XK_bracketleft => Key::BracketLeft,
+        keysym::XK_backslash => Key::BackSlash,
+        keysym::XK_bracketright => Key::BracketRight,
+        keysym::XK_grave => Key::Grave,
+        keysym::XK_braceleft => Key::BraceLeft,
+        keysym::XK_bar => Key::Bar,
+        keysym::XK_braceright => Key::BraceRight,
+        keysym::XK_exclamdown => Key::ExclamDown,
+        keysym::XK_guillemotleft => Key::GuillemotLeft,
+        keysym::XK_masculine => Key::Masculine,
+        keysym::XK_questiondown => Key::QuestionDown,
+        keysym::XK_agrave => Key::AGrave,
+        keysym::XK_ccedilla => Key::CCedilla,
+        keysym::XK_egrave => Key::EGrave,
+        keysym::XK_eacute => Key::EAcute,
+        keysym::XK_igrave => Key::IGrave,
+        keysym::XK_ntilde => Key::NTilde,
+        keysym::XK_ograve => Key::OGrave,
+        keysym::XK_ugrave => Key::UGrave,
+        // things not in x11::keysym
+        0xfe03 /* XK_ISO_Level3_Shift */ => Key::AltRight,  // AltGr -- Non-US sends this instead of Alt_R
+        0xfe50 /* XK_dead_grave */ => Key::DeadGrave,
+        0xfe51 /* XK_dead_acute */ => Key::DeadAcute,
+        0xfe52 /* XK_dead_circumflex */ => Key::DeadCircumflex,
+        0xfe53 /* XK_dead_tilde */ => Key::DeadTilde,
+        0xfe5b /* XK_dead_cedilla */ => Key::DeadCedilla,
+
+        keysym::XF86XK_AudioMute => Key::VolumeMute,
+        keysym::XF86XK_AudioLowerVolume => Key::VolumeDown,
+        keysym::XF86XK_AudioRaiseVolume => Key::VolumeUp,
+        keysym::XF86XK_AudioMicMute => Key::MicMute,
+        keysym::XF86XK_AudioPlay => Key::MediaPlay,
+        keysym::XF86XK_AudioPause => Key::MediaPause,
+        keysym::XF86XK_AudioStop => Key::MediaStop,
+        keysym::XF86XK_AudioPrev => Key::MediaPrev,
+        keysym::XF86XK_AudioNext => Key::MediaNext,
+        keysym::XF86XK_AudioRecord => Key::MediaRecord,
+        keysym::XF86XK_AudioRewind => Key::MediaRewind,
+        keysym::XF86XK_AudioForward => Key::MediaForward,
+        keysym::XF86XK_AudioMedia => Key::MediaSelect,
+        keysym::XF86XK_Back => Key::BrowserBack,
+        keysym::XF86XK_Forward => Key::BrowserForward,
+        keysym::XF86XK_Refresh => Key::BrowserRefresh,
+        keysym::XF86XK_Stop => Key::BrowserStop,
+        keysym::XF86XK_Search => Key::BrowserSearch,
+        keysym::XF86XK_Favorites => Key::BrowserFavorites,
+        keysym::XF86XK_HomePage => Key::BrowserHome,
+        keysym::XF86XK_Mail => Key::LaunchMail,
+        keysym::XF86XK_Calculator => Key::LaunchCalculator,
+        keysym::XF86XK_MyComputer => Key::LaunchComputer,
+        keysym::XF86XK_Explorer => Key::LaunchComputer,
+        keysym::XF86XK_Eject => Key::Eject,
+        keysym::XF86XK_Sleep => Key::Sleep,
+        keysym::XF86XK_WakeUp => Key::WakeUp,
+        keysym::XF86XK_PowerOff => Key::PowerOff,
+        keysym::XF86XK_MonBrightnessDown => Key::BrightnessDown,
+        keysym::XF86XK_MonBrightnessUp => Key::BrightnessUp,
+        other => Key::Unk(other)
+    }
+}
+
+// X keycodes are the linux evdev codes + 8 with the evdev xkb rules, which is what every server uses nowadays
+pub fn convert_keycode(keycode: xlib::KeyCode) -> ScanCode
+{
+    match keycode {
+        9 => ScanCode::Escape,
+        10 => ScanCode::Key1,
+        11 => ScanCode::Key2,
+        12 => ScanCode::Key3,
+        13 => ScanCode::Key4,
+        14 => ScanCode::Key5,
+        15 => ScanCode::Key6,
+        16 => ScanCode::Key7,
+        17 => ScanCode::Key8,
+        18 => ScanCode::Key9,
+        19 => ScanCode::Key0,
+        20 => ScanCode::Minus,
+        21 => ScanCode::Equal,
+        22 => ScanCode::BackSpace,
+        23 => ScanCode::Tab,
+        24 => ScanCode::Q,
+        25 => ScanCode::W,
+        26 => ScanCode::E,
+        27 => ScanCode::R,
+        28 => ScanCode::T,
+        29 => ScanCode::Y,
+        30 => ScanCode::U,
+        31 => ScanCode::I,
+        32 => ScanCode::O,
+        33 => ScanCode::P,
+        34 => ScanCode::BracketLeft,
+        35 => ScanCode::BracketRight,
+        36 => ScanCode::Return,
+        37 => ScanCode::ControlLeft,
+        38 => ScanCode::A,
+        39 => ScanCode::S,
+        40 => ScanCode::D,
+        41 => ScanCode::F,
+        42 => ScanCode::G,
+        43 => ScanCode::H,
+        44 => ScanCode::J,
+        45 => ScanCode::K,
+        46 => ScanCode::L,
+        47 => ScanCode::Semicolon,
+        48 => ScanCode::Apostrophe,
+        49 => ScanCode::Grave,
+        50 => ScanCode::ShiftLeft,
+        51 => ScanCode::BackSlash,
+        52 => ScanCode::Z,
+        53 => ScanCode::X,
+        54 => ScanCode::C,
+        55 => ScanCode::V,
+        56 => ScanCode::B,
+        57 => ScanCode::N,
+        58 => ScanCode::M,
+        59 => ScanCode::Comma,
+        60 => ScanCode::Period,
+        61 => ScanCode::Slash,
+        62 => ScanCode::ShiftRight,
+        63 => ScanCode::NumpadMultiply,
+        64 => ScanCode::AltLeft,
+        65 => ScanCode::Space,
+        66 => ScanCode::CapsLock,
+        67 => ScanCode::F1,
+        68 => ScanCode::F2,
+        69 => ScanCode::F3,
+        70 => ScanCode::F4,
+        71 => ScanCode::F5,
+        72 => ScanCode::F6,
+        73 => ScanCode::F7,
+        74 => ScanCode::F8,
+        75 => ScanCode::F9,
+        76 => ScanCode::F10,
+        77 => ScanCode::NumLock,
+        78 => ScanCode::ScrollLock,
+        79 => ScanCode::Numpad7,
+        80 => ScanCode::Numpad8,
+        81 => ScanCode::Numpad9,
+        82 => ScanCode::NumpadSubtract,
+        83 => ScanCode::Numpad4,
+        84 => ScanCode::Numpad5,
+        85 => ScanCode::Numpad6,
+        86 => ScanCode::NumpadAdd,
+        87 => ScanCode::Numpad1,
+        88 => ScanCode::Numpad2,
+        89 => ScanCode::Numpad3,
+        90 => ScanCode::Numpad0,
+        91 => ScanCode::NumpadDecimal,
+        94 => ScanCode::IntlBackslash,
+        95 => ScanCode::F11,
+        96 => ScanCode::F12,
+        97 => ScanCode::IntlRo,
+        104 => ScanCode::NumpadEnter,
+        105 => ScanCode::ControlRight,
+        106 => ScanCode::NumpadDivide,
+        107 => ScanCode::Print,
+        108 => ScanCode::AltRight,
+        110 => ScanCode::Home,
+        111 => ScanCode::Up,
+        112 => ScanCode::PageUp,
+        113 => ScanCode::Left,
+        114 => ScanCode::Right,
+        115 => ScanCode::End,
+        116 => ScanCode::Down,
+        117 => ScanCode::PageDown,
+        118 => ScanCode::Insert,
+        119 => ScanCode::Delete,
+        125 => ScanCode::NumpadEqual,
+        127 => ScanCode::Pause,
+        129 => ScanCode::NumpadComma,
+        132 => ScanCode::IntlYen,
+        133 => ScanCode::SuperLeft,
+        134 => ScanCode::SuperRight,
+        135 => ScanCode::Menu,
+        191 => ScanCode::F13,
+        192 => ScanCode::F14,
+        193 => ScanCode::F15,
+        194 => ScanCode::F16,
+        195 => ScanCode::F17,
+        196 => ScanCode::F18,
+        197 => ScanCode::F19,
+        198 => ScanCode::F20,
+        199 => ScanCode::F21,
+        200 => ScanCode::F22,
+        201 => ScanCode::F23,
+        202 => ScanCode::F24,
+        other => ScanCode::Unk(other as u32)
+    }
+}
+
+// the inverse of convert_keycode
+pub fn scancode_to_keycode(scancode: ScanCode) -> Option<xlib::KeyCode>
+{
+    match scancode {
+        ScanCode::Unk(keycode) if (8 ..= 255).contains(&keycode) => Some(keycode as xlib::KeyCode),
+        ScanCode::Unk(_) => None,
+        _ => (8 ..= 255).find(|&kc| convert_keycode(kc) == scancode),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AxisType
+{
+    ScrollVertical(f64 /* increment */),
+    ScrollHorizontal(f64 /* increment */),
+    Pressure(f64 /* min */, f64 /* max */),
+    TiltX(f64 /* max */),
+    TiltY(f64 /* max */),
+}
+
+#[derive(Debug)]
+struct AxisData
+{
+    axis_type: AxisType,
+    value: f64,
+}
+
+#[derive(Debug, Default)]
+struct DeviceInfo
+{
+    axis_info: HashMap<i32 /* axis_num */, AxisData>,
+    num_axis: i32,
+    has_scroll: bool,
+    absolute: bool,
+}
+
+// atoms used to identify the valuators we care about
+#[derive(Debug, Clone, Copy)]
+pub struct AxisLabels
+{
+    pub pressure: xlib::Atom,
+    pub mt_pressure: xlib::Atom,
+    pub tilt_x: xlib::Atom,
+    pub tilt_y: xlib::Atom,
+}
+
+// axis values of the known devices and the last pointer position, needed to turn
+// absolute valuators into deltas and to drop duplicated motion events
+#[derive(Debug, Default)]
+pub struct InputState
+{
+    devices: HashMap<i32 /* device_id */, DeviceInfo>,
+    pointer_pos: (f64, f64),
+    keys_down: HashSet<xlib::KeyCode>,
+}
+
+impl InputState
+{
+    pub fn new() -> Self
+    {
+        InputState{ devices: HashMap::new(), pointer_pos: (-1.0, -1.0), keys_down: HashSet::new() }
+    }
+
+    // with detectable auto repeat there are no KeyRelease between repeats, so a press
+    // for a key that is already down is a repeat. returns true in that case
+    pub fn key_pressed(&mut self, keycode: xlib::KeyCode) -> bool
+    {
+        !self.keys_down.insert(keycode)
+    }
+
+    pub fn key_released(&mut self, keycode: xlib::KeyCode)
+    {
+        self.keys_down.remove(&keycode);
+    }
+
+    // we don't get the releases while unfocused
+    pub fn clear_keys(&mut self)
+    {
+        self.keys_down.clear();
+    }
+
+    // on focus in, so keys that were already held count as repeats
+    pub fn set_keys_down(&mut self, keycodes: &[xlib::KeyCode])
+    {
+        self.keys_down = keycodes.iter().cloned().collect();
+    }
+
+    // reads the axis info from an XIQueryDevice result
+    /// # Safety
+    /// dev.classes must point to dev.num_classes valid class infos
+    pub unsafe fn add_device(&mut self, dev: &xinput2::XIDeviceInfo, labels: &AxisLabels)
+    {
+        // slave pointers are the physical devices that can have multiple axis
+        if dev._use == xinput2::XISlavePointer
+        {
+            let mut values = vec![0.0; dev.num_classes as usize];
+
+            for i in 0 .. dev.num_classes as isize
+            {
+                let class = unsafe { *dev.classes.offset(i) };
+                let ctype = unsafe { (*class)._type };
+
+                let (ax_num, ax_data) = match ctype {
+                    xinput2::XIValuatorClass => {
+                        let ci: &xinput2::XIValuatorClassInfo = unsafe { &*(class as *const _) };
+                        // we're gonna assume valuators appear before scroll classes, so we can store them here ...
+                        values[ci.number as usize] = ci.value;
+                        if ci.number == 0 && ci.mode == xinput2::XIModeAbsolute
+                        {
+                            self.devices.entry(dev.deviceid).or_default().absolute = true;
+                        }
+
+                        let ax_type = match ci.label {
+                            // touchscreens report the pressure of each touch
+                            a if a == labels.pressure || a == labels.mt_pressure => AxisType::Pressure(ci.min, ci.max),
+                            // the tilt value should be "almost" symmetric (like -64 to 63)
+                            a if a == labels.tilt_x => AxisType::TiltX(ci.max),
+                            a if a == labels.tilt_y => AxisType::TiltY(ci.max),
+                            _ => continue
+                        };
+                        (ci.number, AxisData{ axis_type: ax_type, value: ci.value })
+                    },
+                    xinput2::XIScrollClass => {
+                        let ci: &xinput2::XIScrollClassInfo = unsafe { &*(class as *const _) };
+                        // ... and use them here to fill in the scroll classes
+                        match ci.scroll_type {
+                            xinput2::XIScrollTypeVertical => {
+                                (ci.number, AxisData{ axis_type: AxisType::ScrollVertical(ci.increment), value: values[ci.number as usize] })
+                            },
+                            xinput2::XIScrollTypeHorizontal => {
+                                (ci.number, AxisData{ axis_type: AxisType::ScrollHorizontal(ci.increment), value: values[ci.number as usize] })
+                            },
+                            _ => continue
+                        }
+                    },
+                    _ => continue
+                };
+
+                self.set_axis(dev.deviceid, ax_num, ax_data.axis_type, ax_data.value);
+            }
+        }
+    }
+
+    // adds or replaces a single axis, value is the current absolute valuator value
+    pub fn set_axis(&mut self, device_id: i32, axis_num: i32, axis_type: AxisType, value: f64)
+    {
+        let dev_info = self.devices.entry(device_id).or_default();
+        dev_info.axis_info.insert(axis_num, AxisData{ axis_type, value });
+        match axis_type {
+            AxisType::ScrollVertical(_) | AxisType::ScrollHorizontal(_) => dev_info.has_scroll = true,
+            _ => (),
+        }
+        // store the highest axis id we need to read from events
+        if axis_num + 1 > dev_info.num_axis
+        {
+            dev_info.num_axis = axis_num + 1;
+        }
+    }
+
+    pub fn remove_device(&mut self, device_id: i32)
+    {
+        self.devices.remove(&device_id);
+    }
+
+    // pressure is 1.0 if the device doesn't report it
+    /// # Safety
+    /// the valuator mask and values must be valid, as in an event from XGetEventData
+    pub unsafe fn touch_event<U>(&self, ev_data: &xinput2::XIDeviceEvent, state: TouchState) -> Event<U>
+    {
+        let axis_state = ev_data.valuators;
+        let axis_mask = unsafe{ slice::from_raw_parts(axis_state.mask, axis_state.mask_len as usize) };
+        let mut pressure = 1.0;
+
+        if let Some(dev_info) = self.devices.get(&ev_data.sourceid)
+        {
+            let mut cur_offset = 0;
+            for axis_id in 0 .. dev_info.num_axis
+            {
+                if (axis_id as usize) < axis_mask.len() * 8 && xinput2::XIMaskIsSet(axis_mask, axis_id)
+                {
+                    if let Some(&AxisData{ axis_type: AxisType::Pressure(min, max), .. }) = dev_info.axis_info.get(&axis_id)
+                    {
+                        pressure = normalize(unsafe { *axis_state.values.offset(cur_offset) }, min, max);
+                    }
+                    cur_offset += 1;
+                }
+            }
+        }
+
+        Event::Touch(state, ev_data.detail as u32, (ev_data.event_x, ev_data.event_y), pressure)
+    }
+
+    // unaccelerated movement from XI_RawMotion. None for tablets and touchscreens, they report positions
+    /// # Safety
+    /// the valuator mask and raw values must be valid, as in an event from XGetEventData
+    pub unsafe fn raw_motion<U>(&self, ev_data: &xinput2::XIRawEvent) -> Option<Event<U>>
+    {
+        if self.devices.get(&ev_data.sourceid).is_some_and(|dev| dev.absolute)
+        {
+            return None
+        }
+
+        let axis_mask = unsafe { slice::from_raw_parts(ev_data.valuators.mask, ev_data.valuators.mask_len as usize) };
+        let mut delta = [0.0; 2];
+        let mut cur_offset = 0;
+        for (axis_id, value) in delta.iter_mut().enumerate()
+        {
+            if axis_id < axis_mask.len() * 8 && xinput2::XIMaskIsSet(axis_mask, axis_id as i32)
+            {
+                *value = unsafe { *ev_data.raw_values.offset(cur_offset) };
+                cur_offset += 1;
+            }
+        }
+
+        if delta == [0.0; 2] { None } else { Some(Event::MouseDelta(delta[0], delta[1])) }
+    }
+
+    // devices that need their scroll values reloaded after the pointer comes back
+    pub fn scroll_devices(&self) -> Vec<i32>
+    {
+        self.devices.iter()
+            .filter(|&(_, info)| info.has_scroll)
+            .map(|(&dev, _)| dev).collect()
+    }
+
+    /// # Safety
+    /// the valuator mask and values must be valid, as in an event from XGetEventData
+    pub unsafe fn motion_events<U>(&mut self, ev_data: &xinput2::XIDeviceEvent) -> Vec<Event<U>>
+    {
+        let axis_state = ev_data.valuators;
+        let axis_mask = unsafe{ slice::from_raw_parts(axis_state.mask, axis_state.mask_len as usize) };
+
+        let (mut scroll_x, mut scroll_y) = (0.0, 0.0);
+        let mut pressure = None;
+        let (mut tilt_x_changed, mut tilt_y_changed) = (false, false);
+        let (mut tilt_x, mut tilt_y) = (0.0, 0.0);
+
+        // devices without axis only move the pointer
+        let mut no_axis = DeviceInfo::default();
+        let dev_info = self.devices.get_mut(&ev_data.sourceid).unwrap_or(&mut no_axis);
+
+        let mut cur_offset = 0;
+        for axis_id in 0 .. dev_info.num_axis
+        {
+            if xinput2::XIMaskIsSet(axis_mask, axis_id)
+            {
+                let axis_value = unsafe { *axis_state.values.offset(cur_offset) };
+                if let Some(axis_info) = dev_info.axis_info.get_mut(&axis_id)
+                {
+                    match axis_info.axis_type {
+                        AxisType::ScrollVertical(incr) => if axis_info.value != axis_value
+                        {
+                            scroll_y = (axis_info.value - axis_value) / incr;
+                        },
+                        AxisType::ScrollHorizontal(incr) => if axis_info.value != axis_value
+                        {
+                            scroll_x = (axis_info.value - axis_value) / incr;
+                        },
+                        AxisType::Pressure(min, max) => if axis_info.value != axis_value
+                        {
+                            pressure = Some(normalize(axis_value, min, max));
+                        },
+                        // assuming those two are always present in pairs
+                        AxisType::TiltX(max) => {
+                            let val = if axis_info.value != axis_value
+                            {
+                                tilt_x_changed = true;
+                                axis_value
+                            }
+                            else
+                            {
+                                axis_info.value
+                            };
+                            tilt_x = (val / max).min(1.0).max(-1.0);
+                        },
+                        AxisType::TiltY(max) => {
+                            let val = if axis_info.value != axis_value
+                            {
+                                tilt_y_changed = true;
+                                axis_value
+                            }
+                            else
+                            {
+                                axis_info.value
+                            };
+                            tilt_y = (val / max).min(1.0).max(-1.0);
+                        },
+                    }
+
+                    axis_info.value = axis_value;
+                }
+
+                cur_offset += 1;
+            }
+        }
+
+        let mut events = Vec::with_capacity(4);
+
+        let pointer_pos = (ev_data.root_x, ev_data.root_y);
+        if self.pointer_pos != pointer_pos
+        {
+            self.pointer_pos = pointer_pos;
+            events.push(Event::MouseMoved(ev_data.event_x, ev_data.event_y));
+        }
+        if scroll_x != 0.0 || scroll_y != 0.0
+        {
+            events.push(Event::AxisMoved(AxisState::Scroll(scroll_x, scroll_y)))
+        }
+        if let Some(val) = pressure
+        {
+            events.push(Event::AxisMoved(AxisState::Pressure(val)))
+        }
+        if tilt_x_changed || tilt_y_changed
+        {
+            events.push(Event::AxisMoved(AxisState::Tilt(tilt_x, tilt_y)))
+        }
+
+        events
+    }
+}
+
+// maps the valuator range to 0.0 - 1.0
+fn normalize(value: f64, min: f64, max: f64) -> f64
+{
+    if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 1.0 }
+}
+
+// IsKeypadKey from Xutil.h
+fn is_keypad(keysym: xlib::KeySym) -> bool
+{
+    keysym >= keysym::XK_KP_Space as xlib::KeySym && keysym <= keysym::XK_KP_Equal as xlib::KeySym
+}
+
+// keys are reported by their base level, except the numpad where NumLock (or Shift) picks the digits
+pub fn keycode_to_key<K: Keymap>(keymap: &K, keycode: xlib::KeyCode, group: u8, mods: Modifiers) -> Key
+{
+    let mut keysym = keymap.keycode_to_keysym(keycode, group, 0);
+    if is_keypad(keysym) && mods.contains(Modifiers::NUM_LOCK) != mods.contains(Modifiers::SHIFT)
+    {
+        match keymap.keycode_to_keysym(keycode, group, 1) {
+            0 => (),
+            ks => keysym = ks,
+        }
+    }
+    convert_keysym(keysym as u32)
+}
+
+pub fn key_event<U, K: Keymap>(keymap: &K, ev: &xlib::XKeyEvent, state: EvState, repeat: bool) -> Event<U>
+{
+    let keycode = ev.keycode as xlib::KeyCode;
+    let mods = keymap.modifier_map().convert(ev.state);
+    let key = keycode_to_key(keymap, keycode, group_from_state(ev.state), mods);
+    Event::Keyboard(state, key, convert_keycode(keycode), mods, Repeat(repeat))
+}
+
+// key events from XI2, they only come to us through a keyboard grab when the focus is outside our windows
+pub fn xi_key_event<U, K: Keymap>(keymap: &K, ev_data: &xinput2::XIDeviceEvent, state: EvState) -> Event<U>
+{
+    let keycode = ev_data.detail as xlib::KeyCode;
+    let mods = keymap.modifier_map().convert(ev_data.mods.effective as c_uint);
+    let key = keycode_to_key(keymap, keycode, ev_data.group.effective as u8, mods);
+    let repeat = state == EvState::Pressed && ev_data.flags & xinput2::XIKeyRepeat != 0;
+    Event::Keyboard(state, key, convert_keycode(keycode), mods, Repeat(repeat))
+}
+
+// the keycodes set in an XQueryKeymap bit vector
+pub fn keymap_keycodes(bits: &[u8; 32]) -> Vec<xlib::KeyCode>
+{
+    (8 .. 256).filter(|&kc| bits[kc / 8] & (1 << (kc % 8)) != 0).map(|kc| kc as xlib::KeyCode).collect()
+}
+
+// text from the input method. control characters (backspace, return, etc) are left to the Keyboard events
+pub fn text_event<U>(text: &str) -> Option<Event<U>>
+{
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    if text.is_empty() { None } else { Some(Event::Text(text)) }
+}
+
+pub fn button_event<U>(ev_data: &xinput2::XIDeviceEvent, state: EvState, mod_map: &ModifierMap) -> Option<Event<U>>
+{
+    let button_id = ev_data.detail;
+    if button_id >= 4 && button_id <= 7  // is wheel
+    {
+        if ev_data.flags & xinput2::XIPointerEmulated != 0  // emulated event, real data is in XI_Motion
+        {
+            None
+        }
+        else
+        {
+            Some(Event::AxisMoved(as_axis_scroll(button_id)))
+        }
+    }
+    else
+    {
+        let mods = mod_map.convert(ev_data.mods.effective as c_uint);
+        Some(Event::MouseButton(state, as_button(button_id), (ev_data.event_x, ev_data.event_y), mods))
+    }
+}
+
+// size and pos are the last known window geometry, updated here
+pub fn configure_events<U>(ev: &xlib::XConfigureEvent, size: &mut (u32, u32), pos: &mut (i32, i32)) -> Vec<Event<U>>
+{
+    let mut events = Vec::with_capacity(2);
+    let new_size = (ev.width as u32, ev.height as u32);
+    if *size != new_size
+    {
+        *size = new_size;
+        events.push(Event::Resized(new_size.0, new_size.1));
+    }
+
+    if ev.above == 0  // when .above is set, event contains a bogus position value
+    {
+        let new_pos = (ev.x, ev.y);
+        if *pos != new_pos
+        {
+            *pos = new_pos;
+            events.push(Event::Moved(new_pos.0, new_pos.1));
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::mem;
+
+    impl Keymap for HashMap<(xlib::KeyCode, u8 /* group */, u8 /* level */), xlib::KeySym>
+    {
+        fn keycode_to_keysym(&self, keycode: xlib::KeyCode, group: u8, level: u8) -> xlib::KeySym
+        {
+            self.get(&(keycode, group, level)).cloned().unwrap_or(0)
+        }
+    }
+
+    fn configure(width: i32, height: i32, x: i32, y: i32, above: xlib::Window) -> xlib::XConfigureEvent
+    {
+        let mut ev: xlib::XConfigureEvent = unsafe { mem::zeroed() };
+        ev.width = width;
+        ev.height = height;
+        ev.x = x;
+        ev.y = y;
+        ev.above = above;
+        ev
+    }
+
+    fn device_event(detail: i32, flags: i32) -> xinput2::XIDeviceEvent
+    {
+        let mut ev: xinput2::XIDeviceEvent = unsafe { mem::zeroed() };
+        ev.detail = detail;
+        ev.flags = flags;
+        ev.event_x = 10.0;
+        ev.event_y = 20.0;
+        ev
+    }
+
+    #[test]
+    fn configure_dedup()
+    {
+        let (mut size, mut pos) = ((0, 0), (0, 0));
+        let events: Vec<Event> = configure_events(&configure(640, 480, 5, 6, 0), &mut size, &mut pos);
+        assert_eq!(events, vec![Event::Resized(640, 480), Event::Moved(5, 6)]);
+        assert!(configure_events::<()>(&configure(640, 480, 5, 6, 0), &mut size, &mut pos).is_empty());
+
+        // the position is bogus when above is set
+        let events: Vec<Event> = configure_events(&configure(800, 480, 50, 60, 1), &mut size, &mut pos);
+        assert_eq!(events, vec![Event::Resized(800, 480)]);
+        assert_eq!(pos, (5, 6));
+    }
+
+    #[test]
+    fn wheel_buttons()
+    {
+        let mod_map = ModifierMap::default();
+        let ev = device_event(4, 0);
+        assert_eq!(button_event::<()>(&ev, EvState::Pressed, &mod_map), Some(Event::AxisMoved(AxisState::Scroll(0.0, 1.0))));
+        // the real data comes in XI_Motion
+        let ev = device_event(5, xinput2::XIPointerEmulated);
+        assert_eq!(button_event::<()>(&ev, EvState::Pressed, &mod_map), None);
+
+        let mut ev = device_event(1, xinput2::XIPointerEmulated);
+        ev.mods.effective = (xlib::ShiftMask | xlib::Mod1Mask) as i32;
+        assert_eq!(button_event::<()>(&ev, EvState::Released, &mod_map),
+            Some(Event::MouseButton(EvState::Released, Button::Left, (10.0, 20.0), Modifiers::SHIFT | Modifiers::ALT)));
+        assert_eq!(button_event::<()>(&device_event(9, 0), EvState::Pressed, &mod_map),
+            Some(Event::MouseButton(EvState::Pressed, Button::Other(9), (10.0, 20.0), Modifiers::empty())));
+    }
+
+    #[test]
+    fn xi_key_events()
+    {
+        let keymap = test_keymap();
+        let mut ev = device_event(38, xinput2::XIKeyRepeat);
+        ev.mods.effective = xlib::ControlMask as i32;
+        ev.group.effective = 1;
+        assert_eq!(xi_key_event::<(), _>(&keymap, &ev, EvState::Pressed),
+            Event::Keyboard(EvState::Pressed, Key::Unk(keysym::XK_Cyrillic_ef), ScanCode::A, Modifiers::CTRL, Repeat(true)));
+        let ev = device_event(38, 0);
+        assert_eq!(xi_key_event::<(), _>(&keymap, &ev, EvState::Released),
+            Event::Keyboard(EvState::Released, Key::A, ScanCode::A, Modifiers::empty(), Repeat(false)));
+    }
+
+    #[test]
+    fn keymap_bits()
+    {
+        let mut bits = [0u8; 32];
+        bits[0] = 0xff;  // keycodes below 8 don't exist
+        bits[1] = 0x02;
+        bits[31] = 0x80;
+        assert_eq!(keymap_keycodes(&bits), vec![9, 255]);
+        assert!(keymap_keycodes(&[0; 32]).is_empty());
+    }
+
+    #[test]
+    fn keycodes()
+    {
+        assert_eq!(convert_keycode(9), ScanCode::Escape);
+        assert_eq!(convert_keycode(38), ScanCode::A);
+        assert_eq!(convert_keycode(87), ScanCode::Numpad1);
+        assert_eq!(convert_keycode(250), ScanCode::Unk(250));
+        for kc in 8 ..= 255
+        {
+            assert_eq!(scancode_to_keycode(convert_keycode(kc)), Some(kc));
+        }
+        assert_eq!(scancode_to_keycode(ScanCode::Unk(3)), None);
+    }
+
+    #[test]
+    fn out_of_range_groups()
+    {
+        // one group, wraps by default
+        assert_eq!(effective_group(0x01, 0), Some(0));
+        assert_eq!(effective_group(0x01, 1), Some(0));
+        assert_eq!(effective_group(0x02, 3), Some(1));
+        assert_eq!(effective_group(0x03, 1), Some(1));
+        // clamp
+        assert_eq!(effective_group(0x42, 3), Some(1));
+        // redirect to group 1, or 0 if it doesn't exist
+        assert_eq!(effective_group(0x92, 3), Some(1));
+        assert_eq!(effective_group(0xa2, 3), Some(0));
+        assert_eq!(effective_group(0x00, 0), None);
+    }
+
+    fn test_keymap() -> HashMap<(xlib::KeyCode, u8, u8), xlib::KeySym>
+    {
+        let mut keymap = HashMap::new();
+        keymap.insert((38, 0, 0), keysym::XK_a as xlib::KeySym);
+        keymap.insert((38, 0, 1), keysym::XK_A as xlib::KeySym);
+        keymap.insert((38, 1, 0), keysym::XK_Cyrillic_ef as xlib::KeySym);
+        keymap.insert((87, 0, 0), keysym::XK_KP_End as xlib::KeySym);
+        keymap.insert((87, 0, 1), keysym::XK_KP_1 as xlib::KeySym);
+        keymap
+    }
+
+    #[test]
+    fn keymap_lookup()
+    {
+        let keymap = test_keymap();
+        // letters are reported by their base level
+        assert_eq!(keycode_to_key(&keymap, 38, 0, Modifiers::SHIFT), Key::A);
+        assert_eq!(keycode_to_key(&keymap, 38, 1, Modifiers::empty()), Key::Unk(keysym::XK_Cyrillic_ef));
+        assert_eq!(keycode_to_key(&keymap, 10, 0, Modifiers::empty()), Key::Unk(0));
+
+        // NumLock picks the digits, Shift inverts it
+        assert_eq!(keycode_to_key(&keymap, 87, 0, Modifiers::empty()), Key::NumpadEnd);
+        assert_eq!(keycode_to_key(&keymap, 87, 0, Modifiers::NUM_LOCK), Key::Numpad1);
+        assert_eq!(keycode_to_key(&keymap, 87, 0, Modifiers::SHIFT), Key::Numpad1);
+        assert_eq!(keycode_to_key(&keymap, 87, 0, Modifiers::NUM_LOCK | Modifiers::SHIFT), Key::NumpadEnd);
+    }
+
+    #[test]
+    fn key_events()
+    {
+        let keymap = test_keymap();
+        let mut ev: xlib::XKeyEvent = unsafe { mem::zeroed() };
+        ev.keycode = 38;
+        ev.state = xlib::ControlMask | 1 << 13;
+        assert_eq!(key_event::<(), _>(&keymap, &ev, EvState::Pressed, true),
+            Event::Keyboard(EvState::Pressed, Key::Unk(keysym::XK_Cyrillic_ef), ScanCode::A, Modifiers::CTRL, Repeat(true)));
+    }
+
+    #[test]
+    fn axis_normalization()
+    {
+        let mut input = InputState::new();
+        input.set_axis(2, 2, AxisType::Pressure(0.0, 1024.0), 0.0);
+        input.set_axis(2, 3, AxisType::TiltX(64.0), 0.0);
+        input.set_axis(2, 4, AxisType::TiltY(64.0), 0.0);
+
+        let mut mask = [0b0001_1100u8];
+        let mut values = [512.0, 32.0, -128.0];
+        let mut ev = device_event(0, 0);
+        ev.sourceid = 2;
+        ev.valuators.mask_len = mask.len() as i32;
+        ev.valuators.mask = mask.as_mut_ptr();
+        ev.valuators.values = values.as_mut_ptr();
+
+        let events: Vec<Event> = unsafe { input.motion_events(&ev) };
+        assert_eq!(events, vec![
+            Event::MouseMoved(10.0, 20.0),
+            Event::AxisMoved(AxisState::Pressure(0.5)),
+            Event::AxisMoved(AxisState::Tilt(0.5, -1.0)),
+        ]);
+        // nothing changed
+        assert!(unsafe { input.motion_events::<()>(&ev) }.is_empty());
+    }
+
+    #[test]
+    fn touch_pressure()
+    {
+        let mut input = InputState::new();
+        input.set_axis(3, 2, AxisType::Pressure(100.0, 300.0), 0.0);
+
+        let mut mask = [0b0000_0111u8];
+        let mut values = [10.0, 20.0, 200.0];
+        let mut ev = device_event(5, 0);
+        ev.sourceid = 3;
+        ev.valuators.mask_len = mask.len() as i32;
+        ev.valuators.mask = mask.as_mut_ptr();
+        ev.valuators.values = values.as_mut_ptr();
+        assert_eq!(unsafe { input.touch_event::<()>(&ev, TouchState::Begin) }, Event::Touch(TouchState::Begin, 5, (10.0, 20.0), 0.5));
+
+        // devices without pressure
+        ev.sourceid = 4;
+        assert_eq!(unsafe { input.touch_event::<()>(&ev, TouchState::End) }, Event::Touch(TouchState::End, 5, (10.0, 20.0), 1.0));
+    }
+}
@@ -0,0 +1,118 @@
+// Xlib error handlers are process-wide, so errors are stored here by display and picked up by XDisplay
+use x11::xlib;
+use std::ptr;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_ulong, c_void};
+use std::collections::{HashMap, VecDeque};
+use std::cell::RefCell;
+use std::sync::Once;
+use error::ProtocolError;
+
+// not in x11-rs, needs libX11 >= 1.7
+#[link(name="X11")]
+extern "C" {
+    fn XSetIOErrorExitHandler(display: *mut xlib::Display,
+        handler: Option<unsafe extern "C" fn(*mut xlib::Display, *mut c_void)>, user_data: *mut c_void);
+}
+
+#[derive(Default)]
+struct ErrorState
+{
+    errors: VecDeque<ProtocolError>,
+    disconnected: bool,
+}
+
+thread_local!(static ERROR_STATE: RefCell<HashMap<usize /* display */, ErrorState>> = RefCell::new(HashMap::new()));
+
+static INSTALL_HANDLERS: Once = Once::new();
+
+fn with_state<T, F>(display: *mut xlib::Display, f: F) -> Option<T>
+    where F: FnOnce(&mut ErrorState) -> T
+{
+    ERROR_STATE.with(|st| st.borrow_mut().get_mut(&(display as usize)).map(f))
+}
+
+fn get_text<F: FnOnce(*mut c_char, c_int)>(f: F) -> String
+{
+    let mut buf = [0u8; 256];
+    f(buf.as_mut_ptr() as *mut _, buf.len() as c_int);
+    unsafe { CStr::from_ptr(buf.as_ptr() as *const _) }.to_string_lossy().into_owned()
+}
+
+unsafe extern "C" fn error_handler(display: *mut xlib::Display, ev: *mut xlib::XErrorEvent) -> c_int
+{
+    let ev = &*ev;
+
+    let description = get_text(|buf, len| { xlib::XGetErrorText(display, ev.error_code as c_int, buf, len); });
+    // core requests have their names in the error database, extensions only give us the opcode
+    let request = if ev.request_code < 128
+    {
+        let code = CString::new(ev.request_code.to_string()).unwrap();
+        get_text(|buf, len| {
+            xlib::XGetErrorDatabaseText(display, b"XRequest\0".as_ptr() as *const _, code.as_ptr(), b"\0".as_ptr() as *const _, buf, len);
+        })
+    }
+    else { String::new() };
+
+    let err = ProtocolError{
+        error_code: ev.error_code,
+        request_code: ev.request_code,
+        minor_code: ev.minor_code,
+        resource_id: ev.resourceid,
+        serial: ev.serial,
+        request: if request.is_empty() { format!("opcode {}", ev.request_code) } else { request },
+        description,
+    };
+
+    if with_state(display, |st| st.errors.push_back(err.clone())).is_none()
+    {
+        // not one of ours, still better than killing the process
+        eprintln!("X error on unknown display: {}", err);
+    }
+    0
+}
+
+unsafe extern "C" fn io_error_handler(display: *mut xlib::Display) -> c_int
+{
+    with_state(display, |st| st.disconnected = true);
+    0
+}
+
+// called by Xlib after the IO error handler, the default one calls exit()
+unsafe extern "C" fn io_error_exit_handler(_display: *mut xlib::Display, _data: *mut c_void)
+{
+}
+
+pub fn register(display: *mut xlib::Display)
+{
+    INSTALL_HANDLERS.call_once(|| unsafe {
+        xlib::XSetErrorHandler(Some(error_handler));
+        xlib::XSetIOErrorHandler(Some(io_error_handler));
+    });
+    unsafe { XSetIOErrorExitHandler(display, Some(io_error_exit_handler), ptr::null_mut()); }
+    ERROR_STATE.with(|st| st.borrow_mut().insert(display as usize, Default::default()));
+}
+
+pub fn unregister(display: *mut xlib::Display)
+{
+    ERROR_STATE.with(|st| st.borrow_mut().remove(&(display as usize)));
+}
+
+pub fn is_disconnected(display: *mut xlib::Display) -> bool
+{
+    with_state(display, |st| st.disconnected).unwrap_or(false)
+}
+
+// next asynchronous error that nobody checked for
+pub fn pop_error(display: *mut xlib::Display) -> Option<ProtocolError>
+{
+    with_state(display, |st| st.errors.pop_front()).and_then(|e| e)
+}
+
+// removes and returns the first error caused by a request at or after the given serial
+pub fn take_error_since(display: *mut xlib::Display, serial: c_ulong) -> Option<ProtocolError>
+{
+    with_state(display, |st| {
+        st.errors.iter().position(|e| e.serial >= serial).and_then(|i| st.errors.remove(i))
+    }).and_then(|e| e)
+}
@@ -0,0 +1,62 @@
+// XKB bits that x11-rs doesn't have, or declares as opaque
+use x11::xlib;
+use std::os::raw::{c_int, c_uint, c_ulong, c_uchar, c_ushort, c_void};
+
+pub const USE_CORE_KBD: c_uint = 0x0100;
+
+// XkbGetMap components
+pub const KEY_SYMS_MASK: c_uint = 1 << 1;
+
+// XkbStateNotify details
+pub const GROUP_STATE_MASK: c_ulong = 1 << 4;
+// XkbNamesNotify details
+pub const GROUP_NAMES_MASK: c_ulong = 1 << 12;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct StateRec
+{
+    pub group: c_uchar,
+    pub locked_group: c_uchar,
+    pub base_group: c_ushort,
+    pub latched_group: c_ushort,
+    pub mods: c_uchar,
+    pub base_mods: c_uchar,
+    pub latched_mods: c_uchar,
+    pub locked_mods: c_uchar,
+    pub compat_state: c_uchar,
+    pub grab_mods: c_uchar,
+    pub compat_grab_mods: c_uchar,
+    pub lookup_mods: c_uchar,
+    pub compat_lookup_mods: c_uchar,
+    pub ptr_buttons: c_ushort,
+}
+
+// x11-rs declares XkbClientMapRec as opaque
+#[repr(C)]
+pub struct ClientMapRec
+{
+    pub size_types: c_uchar,
+    pub num_types: c_uchar,
+    pub types: *mut c_void,
+    pub size_syms: c_ushort,
+    pub num_syms: c_ushort,
+    pub syms: *mut xlib::KeySym,
+    pub key_sym_map: *mut SymMapRec,
+    pub modmap: *mut c_uchar,
+}
+
+#[repr(C)]
+pub struct SymMapRec
+{
+    pub kt_index: [c_uchar; 4],
+    pub group_info: c_uchar,
+    pub width: c_uchar,
+    pub offset: c_ushort,
+}
+
+#[link(name="X11")]
+extern "C" {
+    pub fn XkbGetState(display: *mut xlib::Display, device_spec: c_uint, state: *mut StateRec) -> c_int;
+}
+